@@ -1,11 +1,28 @@
+pub mod election;
+pub mod error;
 mod execution_info;
+pub mod hybrid;
+pub mod idempotency;
 mod key_generation;
+pub mod payment;
+pub mod retention;
+pub mod rsa_key;
+pub mod secret;
+mod signed_message;
+pub mod state_machine;
+mod task_cancel;
 mod task_info;
+mod task_role;
 pub mod utils;
+pub mod vss;
 
-pub use execution_info::ExecutionInfo;
+pub use execution_info::{ExecutionInfo, SignMode};
 pub use key_generation::{
     decrypt_key_slice, send_key_candidate_request, send_key_generation_request,
-    verify_to_candidate_signature,
+    verify_to_candidate_signature, wipe_key_generation_rsa_key,
 };
+pub use payment::TaskPayment;
+pub use signed_message::{send_signed_message, verify_signed_message};
+pub use task_cancel::{send_task_cancel_request, verify_task_cancel_request};
 pub use task_info::TaskInfo;
+pub use task_role::{TaskRole, TaskRoleRecord};
@@ -0,0 +1,128 @@
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutorElection {
+    pub block_hash: Vec<u8>,
+    pub task_id: String,
+    pub candidates: Vec<Vec<u8>>,
+    pub executor: Vec<u8>,
+}
+
+impl ExecutorElection {
+    pub fn verify(&self) -> bool {
+        verify_executor_election(
+            &self.block_hash,
+            &self.task_id,
+            &self.candidates,
+            &self.executor,
+        )
+    }
+}
+
+/// Distance of a candidate is XOR value of `block hash + task hash + candidate ephemeral id`,
+/// the candidate with the minimum distance should be the executor.
+pub fn executor_distance(block_hash: &[u8], task_id: &str, ephemeral_id: &[u8]) -> Vec<u8> {
+    let task_hash = Sha256::digest(task_id.as_bytes());
+    let len = block_hash
+        .len()
+        .max(task_hash.len())
+        .max(ephemeral_id.len());
+
+    let byte_at = |bytes: &[u8], i: usize| bytes.get(i).cloned().unwrap_or_default();
+    (0..len)
+        .map(|i| byte_at(block_hash, i) ^ byte_at(&task_hash, i) ^ byte_at(ephemeral_id, i))
+        .collect()
+}
+
+/// Returns index of the elected candidate, ties are broken by the smaller ephemeral id.
+pub fn elect_executor(block_hash: &[u8], task_id: &str, candidates: &[Vec<u8>]) -> Option<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            executor_distance(block_hash, task_id, a)
+                .cmp(&executor_distance(block_hash, task_id, b))
+                .then_with(|| a.cmp(b))
+        })
+        .map(|(index, _)| index)
+}
+
+pub fn verify_executor_election(
+    block_hash: &[u8],
+    task_id: &str,
+    candidates: &[Vec<u8>],
+    executor: &[u8],
+) -> bool {
+    match elect_executor(block_hash, task_id, candidates) {
+        Some(index) => candidates[index].as_slice() == executor,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<Vec<u8>> {
+        (0..16u8).map(|i| vec![i.wrapping_mul(37); 32]).collect()
+    }
+
+    #[test]
+    fn election_is_deterministic_and_order_independent() {
+        let block_hash = vec![7u8; 32];
+        let mut candidates = candidates();
+        let index = elect_executor(&block_hash, "task", &candidates).unwrap();
+        let elected = candidates[index].clone();
+
+        candidates.reverse();
+        let index = elect_executor(&block_hash, "task", &candidates).unwrap();
+        assert_eq!(elected, candidates[index]);
+        assert!(verify_executor_election(
+            &block_hash,
+            "task",
+            &candidates,
+            &elected
+        ));
+    }
+
+    #[test]
+    fn elected_candidate_has_minimum_distance() {
+        let block_hash = vec![1u8; 32];
+        let candidates = candidates();
+        let index = elect_executor(&block_hash, "task", &candidates).unwrap();
+        let min = executor_distance(&block_hash, "task", &candidates[index]);
+        for c in candidates.iter() {
+            assert!(min <= executor_distance(&block_hash, "task", c));
+        }
+    }
+
+    #[test]
+    fn verify_rejects_other_candidates() {
+        let block_hash = vec![3u8; 32];
+        let candidates = candidates();
+        let index = elect_executor(&block_hash, "task", &candidates).unwrap();
+        for (i, c) in candidates.iter().enumerate() {
+            assert_eq!(
+                i == index,
+                verify_executor_election(&block_hash, "task", &candidates, c)
+            );
+        }
+        assert!(!verify_executor_election(&block_hash, "task", &[], &[]));
+        assert!(!verify_executor_election(
+            &block_hash,
+            "task",
+            &candidates,
+            &[0xffu8; 32]
+        ));
+    }
+
+    #[test]
+    fn election_depends_on_block_hash_and_task() {
+        let candidates = candidates();
+        let elected: Vec<usize> = (0..16u8)
+            .map(|i| elect_executor(&[i; 32], &format!("task{}", i), &candidates).unwrap())
+            .collect();
+        assert!(elected.iter().any(|v| *v != elected[0]));
+    }
+}
@@ -0,0 +1,17 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskError {
+    /// Task has reached a terminal state or its session data has been wiped.
+    TaskExpired(String),
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::TaskExpired(task_id) => write!(f, "task {} has expired", task_id),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
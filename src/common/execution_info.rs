@@ -1,9 +1,42 @@
+use std::convert::TryFrom;
+
+/// How P2 signs a transaction of an asset.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum SignMode {
+    /// Executor recovers P2 private key from key slices and signs with it
+    Recover,
+    /// Each pinner signs with its key slice, executor only aggregates the partial signatures
+    Threshold,
+}
+
+impl SignMode {
+    pub fn from_threshold_flag(threshold_sign: bool) -> Self {
+        if threshold_sign {
+            SignMode::Threshold
+        } else {
+            SignMode::Recover
+        }
+    }
+
+    pub fn is_threshold(&self) -> bool {
+        *self == SignMode::Threshold
+    }
+}
+
+impl Default for SignMode {
+    fn default() -> Self {
+        SignMode::Recover
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecutionInfo {
     pub n: u8,
     pub k: u8,
     pub task_type: String,
+    #[serde(default)]
+    pub sign_mode: SignMode,
 }
 
 impl Default for ExecutionInfo {
@@ -12,6 +45,69 @@ impl Default for ExecutionInfo {
             n: 0,
             k: 0,
             task_type: "".into(),
+            sign_mode: SignMode::default(),
         }
     }
 }
+
+impl TryFrom<&crate::actor_delegate_proto::AssetInfo> for ExecutionInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &crate::actor_delegate_proto::AssetInfo) -> Result<Self, Self::Error> {
+        if value.n == 0 || value.p2_deployment_ids.is_empty() {
+            return Err(anyhow::anyhow!("{}:{} unknown asset", line!(), file!()));
+        }
+        validate_threshold(value.n as u8, value.k as u8)?;
+        if value.key_type.is_empty() {
+            return Err(anyhow::anyhow!("{}:{} key type is empty", line!(), file!()));
+        }
+
+        Ok(ExecutionInfo {
+            n: value.n as u8,
+            k: value.k as u8,
+            task_type: value.key_type.clone(),
+            sign_mode: SignMode::from_threshold_flag(value.threshold_sign),
+        })
+    }
+}
+
+/// A key split into `n` slices must need fewer than `n` of them to be recovered, so that
+/// losing one pinner does not lose the key.
+pub fn validate_threshold(n: u8, k: u8) -> anyhow::Result<()> {
+    if n == 0 {
+        return Err(anyhow::anyhow!("{}:{} invalid value n", line!(), file!()));
+    }
+    if k == 0 || k >= n {
+        return Err(anyhow::anyhow!("{}:{} invalid value k", line!(), file!()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset_info(n: u32, k: u32) -> crate::actor_delegate_proto::AssetInfo {
+        crate::actor_delegate_proto::AssetInfo {
+            n,
+            k,
+            key_type: "bitcoin_mainnet".into(),
+            p2_deployment_ids: vec!["d1".into(), "d2".into(), "d3".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn k_must_be_less_than_n() {
+        assert!(validate_threshold(3, 2).is_ok());
+        assert!(validate_threshold(3, 3).is_err());
+        assert!(validate_threshold(3, 0).is_err());
+        assert!(validate_threshold(0, 0).is_err());
+    }
+
+    #[test]
+    fn asset_info_uses_the_same_rule() {
+        assert!(ExecutionInfo::try_from(&asset_info(3, 2)).is_ok());
+        assert!(ExecutionInfo::try_from(&asset_info(3, 3)).is_err());
+    }
+}
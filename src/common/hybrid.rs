@@ -0,0 +1,347 @@
+use super::execution_info::SignMode;
+use super::secret::SecretBuffer;
+use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use std::collections::HashSet;
+use zeroize::Zeroize;
+
+const ENVELOPE_VERSION: u8 = 1;
+const AES_KEY_LENGTH: usize = 32;
+// every envelope has its own single use aes key, so a constant nonce is safe
+const AES_GCM_NONCE: [u8; 12] = [0u8; 12];
+
+/// Key slice encrypted with an ephemeral AES-GCM key, the AES key is wrapped by the RSA public
+/// key of the receiver. Task id, receiver peer id and share index are bound as associated data,
+/// so that a key slice can not be swapped between tasks or redirected to another peer.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeySliceEnvelope {
+    version: u8,
+    peer_id: String,
+    index: u32,
+    wrapped_key: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// Key slice as deployed by the initial pinner, so that the pinner can re-encrypt it with the
+/// same share index when it is requested by a sign task. Sign mode and key type of the asset are
+/// deployed with it, sign tasks can not ask the pinner to use the key slice another way.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeployedKeySlice {
+    peer_id: String,
+    index: u32,
+    key_slice: Vec<u8>,
+    #[serde(default)]
+    sign_mode: SignMode,
+    #[serde(default)]
+    key_type: String,
+}
+
+impl Drop for DeployedKeySlice {
+    fn drop(&mut self) {
+        self.key_slice.zeroize();
+    }
+}
+
+#[derive(Debug)]
+pub struct KeySliceInfo {
+    pub peer_id: String,
+    pub index: u32,
+    pub key_slice: SecretBuffer,
+}
+
+#[derive(Debug)]
+pub struct DeployedKeySliceInfo {
+    pub key_slice: KeySliceInfo,
+    pub sign_mode: SignMode,
+    pub key_type: String,
+}
+
+pub fn associated_data(task_id: &str, peer_id: &str, index: u32) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.push(ENVELOPE_VERSION);
+    buf.extend(&(task_id.len() as u32).to_le_bytes());
+    buf.extend(task_id.as_bytes());
+    buf.extend(&(peer_id.len() as u32).to_le_bytes());
+    buf.extend(peer_id.as_bytes());
+    buf.extend(&index.to_le_bytes());
+    buf
+}
+
+/// Encrypts `key_slice` for the receiver `peer_id`, only that peer can open the envelope.
+pub fn encrypt_key_slice<F>(
+    aes_key: &SecretBuffer,
+    task_id: &str,
+    peer_id: &str,
+    index: u32,
+    key_slice: &[u8],
+    wrap_key: F,
+) -> anyhow::Result<Vec<u8>>
+where
+    F: FnOnce(Vec<u8>) -> anyhow::Result<Vec<u8>>,
+{
+    let ciphertext = seal(
+        aes_key,
+        &associated_data(task_id, peer_id, index),
+        key_slice,
+    )?;
+    let envelope = KeySliceEnvelope {
+        version: ENVELOPE_VERSION,
+        peer_id: peer_id.to_string(),
+        index,
+        wrapped_key: wrap_key(aes_key.to_vec())?,
+        ciphertext,
+    };
+    Ok(tea_codec::serialize(envelope)?)
+}
+
+/// Opens an envelope sent to me, the associated data is rebuilt from `my_peer_id` rather than
+/// the peer id claimed by the envelope.
+pub fn decrypt_key_slice<F>(
+    task_id: &str,
+    my_peer_id: &str,
+    data: &[u8],
+    unwrap_key: F,
+) -> anyhow::Result<KeySliceInfo>
+where
+    F: FnOnce(Vec<u8>) -> anyhow::Result<SecretBuffer>,
+{
+    let envelope: KeySliceEnvelope = tea_codec::deserialize(data)?;
+    if envelope.version != ENVELOPE_VERSION {
+        return Err(anyhow::anyhow!(
+            "{}:{} unsupported key slice envelope version {}",
+            line!(),
+            file!(),
+            envelope.version
+        ));
+    }
+    if envelope.peer_id != my_peer_id {
+        return Err(anyhow::anyhow!(
+            "{}:{} key slice is sent to {}, not to me",
+            line!(),
+            file!(),
+            envelope.peer_id
+        ));
+    }
+
+    let aes_key = unwrap_key(envelope.wrapped_key)?;
+    let key_slice = open(
+        &aes_key,
+        &associated_data(task_id, my_peer_id, envelope.index),
+        &envelope.ciphertext,
+    )?;
+    Ok(KeySliceInfo {
+        peer_id: envelope.peer_id,
+        index: envelope.index,
+        key_slice,
+    })
+}
+
+/// Decrypts key slices of the same task, key slices with duplicated share index are rejected.
+pub fn decrypt_key_slices<F>(
+    task_id: &str,
+    my_peer_id: &str,
+    encrypted_key_slices: Vec<Vec<u8>>,
+    unwrap_key: F,
+) -> anyhow::Result<Vec<SecretBuffer>>
+where
+    F: Fn(Vec<u8>) -> anyhow::Result<SecretBuffer>,
+{
+    let mut indexes = HashSet::new();
+    let mut key_slices: Vec<SecretBuffer> = Vec::new();
+    for encrypted_key_slice in encrypted_key_slices {
+        let info = decrypt_key_slice(task_id, my_peer_id, &encrypted_key_slice, &unwrap_key)?;
+        if !indexes.insert(info.index) {
+            return Err(anyhow::anyhow!(
+                "{}:{} duplicated key slice index {}",
+                line!(),
+                file!(),
+                info.index
+            ));
+        }
+        key_slices.push(info.key_slice);
+    }
+    Ok(key_slices)
+}
+
+pub fn pack_deployed_key_slice(
+    info: &KeySliceInfo,
+    sign_mode: SignMode,
+    key_type: &str,
+) -> anyhow::Result<SecretBuffer> {
+    let deployed = DeployedKeySlice {
+        peer_id: info.peer_id.clone(),
+        index: info.index,
+        key_slice: info.key_slice.to_vec(),
+        sign_mode,
+        key_type: key_type.to_string(),
+    };
+    Ok(tea_codec::serialize(&deployed)?.into())
+}
+
+/// Key slices deployed before sign mode was recorded are unpacked in recover mode.
+pub fn unpack_deployed_key_slice(data: &SecretBuffer) -> anyhow::Result<DeployedKeySliceInfo> {
+    let mut deployed: DeployedKeySlice = tea_codec::deserialize(data.expose())?;
+    Ok(DeployedKeySliceInfo {
+        key_slice: KeySliceInfo {
+            peer_id: std::mem::take(&mut deployed.peer_id),
+            index: deployed.index,
+            key_slice: std::mem::take(&mut deployed.key_slice).into(),
+        },
+        sign_mode: deployed.sign_mode,
+        key_type: std::mem::take(&mut deployed.key_type),
+    })
+}
+
+fn seal(aes_key: &SecretBuffer, aad: &[u8], plain: &[u8]) -> anyhow::Result<Vec<u8>> {
+    new_cipher(aes_key)?
+        .encrypt(
+            GenericArray::from_slice(&AES_GCM_NONCE),
+            Payload { msg: plain, aad },
+        )
+        .map_err(|_| anyhow::anyhow!("{}:{} failed to encrypt key slice", line!(), file!()))
+}
+
+fn open(aes_key: &SecretBuffer, aad: &[u8], ciphertext: &[u8]) -> anyhow::Result<SecretBuffer> {
+    new_cipher(aes_key)?
+        .decrypt(
+            GenericArray::from_slice(&AES_GCM_NONCE),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map(SecretBuffer::from)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "{}:{} failed to decrypt key slice, it may belong to another task or pinner",
+                line!(),
+                file!()
+            )
+        })
+}
+
+fn new_cipher(aes_key: &SecretBuffer) -> anyhow::Result<Aes256Gcm> {
+    if aes_key.len() != AES_KEY_LENGTH {
+        return Err(anyhow::anyhow!(
+            "{}:{} invalid aes key length {}",
+            line!(),
+            file!(),
+            aes_key.len()
+        ));
+    }
+    Ok(Aes256Gcm::new(GenericArray::from_slice(aes_key.expose())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aes_key() -> SecretBuffer {
+        SecretBuffer::from(vec![7u8; AES_KEY_LENGTH])
+    }
+
+    fn encrypt(task_id: &str, peer_id: &str, index: u32) -> anyhow::Result<Vec<u8>> {
+        encrypt_key_slice(&aes_key(), task_id, peer_id, index, b"slice", |k| Ok(k))
+    }
+
+    fn decrypt(task_id: &str, data: &[u8]) -> anyhow::Result<KeySliceInfo> {
+        decrypt_key_slice(task_id, "peer", data, |k| Ok(k.into()))
+    }
+
+    #[test]
+    fn round_trip_works() -> anyhow::Result<()> {
+        let info = decrypt("task", &encrypt("task", "peer", 3)?)?;
+        assert_eq!("peer", info.peer_id);
+        assert_eq!(3, info.index);
+        assert_eq!(b"slice", info.key_slice.expose());
+        Ok(())
+    }
+
+    #[test]
+    fn key_slice_is_bound_to_task() -> anyhow::Result<()> {
+        assert!(decrypt("another task", &encrypt("task", "peer", 3)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn key_slice_is_bound_to_receiver() -> anyhow::Result<()> {
+        let data = encrypt("task", "peer A", 3)?;
+        assert!(decrypt_key_slice("task", "peer A", &data, |k| Ok(k.into())).is_ok());
+        assert!(decrypt_key_slice("task", "peer B", &data, |k| Ok(k.into())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_envelope_is_rejected() -> anyhow::Result<()> {
+        let data = encrypt("task", "peer", 3)?;
+        let mut envelope: KeySliceEnvelope = tea_codec::deserialize(&data)?;
+        envelope.peer_id = "another peer".into();
+        assert!(decrypt("task", &tea_codec::serialize(&envelope)?).is_err());
+
+        let mut envelope: KeySliceEnvelope = tea_codec::deserialize(&data)?;
+        envelope.index = 4;
+        assert!(decrypt("task", &tea_codec::serialize(&envelope)?).is_err());
+
+        let mut envelope: KeySliceEnvelope = tea_codec::deserialize(&data)?;
+        envelope.version = 2;
+        assert!(decrypt("task", &tea_codec::serialize(&envelope)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn associated_data_has_no_ambiguity() {
+        assert_ne!(associated_data("ab", "c", 0), associated_data("a", "bc", 0));
+        assert_ne!(associated_data("a", "b", 0), associated_data("a", "b", 1));
+    }
+
+    #[test]
+    fn duplicated_index_is_rejected() -> anyhow::Result<()> {
+        let slices = vec![encrypt("task", "peer", 1)?, encrypt("task", "peer", 2)?];
+        assert_eq!(
+            2,
+            decrypt_key_slices("task", "peer", slices, |k| Ok(k.into()))?.len()
+        );
+
+        let slices = vec![encrypt("task", "peer", 1)?, encrypt("task", "peer", 1)?];
+        assert!(decrypt_key_slices("task", "peer", slices, |k| Ok(k.into())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deployed_key_slice_round_trip() -> anyhow::Result<()> {
+        let info = decrypt("task", &encrypt("task", "peer", 3)?)?;
+        let packed = pack_deployed_key_slice(&info, SignMode::Threshold, "bitcoin_mainnet")?;
+        let unpacked = unpack_deployed_key_slice(&packed)?;
+        assert_eq!(info.peer_id, unpacked.key_slice.peer_id);
+        assert_eq!(info.index, unpacked.key_slice.index);
+        assert_eq!(
+            info.key_slice.expose(),
+            unpacked.key_slice.key_slice.expose()
+        );
+        assert_eq!(SignMode::Threshold, unpacked.sign_mode);
+        assert_eq!("bitcoin_mainnet", unpacked.key_type);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_deployed_key_slice_is_recover_mode() -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct LegacyDeployedKeySlice {
+            peer_id: String,
+            index: u32,
+            key_slice: Vec<u8>,
+        }
+        let legacy = tea_codec::serialize(&LegacyDeployedKeySlice {
+            peer_id: "peer".into(),
+            index: 3,
+            key_slice: b"slice".to_vec(),
+        })?;
+        let unpacked = unpack_deployed_key_slice(&legacy.into())?;
+        assert_eq!(SignMode::Recover, unpacked.sign_mode);
+        assert_eq!(3, unpacked.key_slice.index);
+        Ok(())
+    }
+}
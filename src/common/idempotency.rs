@@ -0,0 +1,139 @@
+use super::retention::TaskKind;
+use crate::BINDING_NAME;
+use sha2::{Digest, Sha256};
+use tea_actor_utility::actor_kvp;
+use tea_actor_utility::actor_kvp::ShabbyLock;
+use tea_actor_utility::actor_nats::response_reply_with_subject;
+
+const PREFIX_PROCESSED_MESSAGES: &'static str = "gluon_processed_messages";
+const PREFIX_CLAIMED_MESSAGE: &'static str = "gluon_claimed_message";
+const CLAIM_EXPIRY_SECONDS: i32 = 600;
+
+/// Idempotency key of a p2p message, a replayed message from the same peer gets the same key.
+/// `content` is the encoded message.
+pub fn message_key(msg_type: &str, peer_id: &str, content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for field in [msg_type.as_bytes(), peer_id.as_bytes()].iter() {
+        hasher.update(&(field.len() as u32).to_le_bytes());
+        hasher.update(field);
+    }
+    hasher.update(content);
+    base64::encode(hasher.finalize())
+}
+
+/// Checks and claims the message in one step under the lock of processed messages, so that two
+/// deliveries of the same message can not both pass the check. Returns `false` if the message
+/// has been processed, and an error if another delivery is processing it.
+///
+/// The claim is turned into a processed mark by [`mark_processed`], dropped by
+/// [`release_on_error`] if processing fails, or expires by itself if processing never finishes
+/// (e.g. an async callback is lost).
+pub fn claim(kind: TaskKind, task_id: &str, key: &str) -> anyhow::Result<bool> {
+    let _lock = ShabbyLock::lock(BINDING_NAME, &get_processed_messages_key(kind, task_id));
+    if get_processed_messages(kind, task_id)?
+        .iter()
+        .any(|v| v == key)
+    {
+        return Ok(false);
+    }
+    let claim_key = get_claim_key(kind, task_id, key);
+    if actor_kvp::get::<bool>(BINDING_NAME, &claim_key)?.is_some() {
+        return Err(anyhow::anyhow!(
+            "{}:{} message of task {} is being processed",
+            line!(),
+            file!(),
+            task_id
+        ));
+    }
+    actor_kvp::set(BINDING_NAME, &claim_key, &true, CLAIM_EXPIRY_SECONDS)?;
+    Ok(true)
+}
+
+/// Called after a message is processed successfully, so that failed messages can be retried.
+pub fn mark_processed(kind: TaskKind, task_id: &str, key: &str) -> anyhow::Result<()> {
+    let store_key = get_processed_messages_key(kind, task_id);
+    let _lock = ShabbyLock::lock(BINDING_NAME, &store_key);
+    let mut keys = get_processed_messages(kind, task_id)?;
+    if !keys.iter().any(|v| v == key) {
+        keys.push(key.to_string());
+        actor_kvp::set_forever(BINDING_NAME, &store_key, &keys)?;
+    }
+    actor_kvp::del(BINDING_NAME, &get_claim_key(kind, task_id, key))?;
+    Ok(())
+}
+
+/// Drops the claim of a message whose processing failed, so that it can be retried.
+pub fn release_on_error<T>(
+    kind: TaskKind,
+    task_id: &str,
+    key: &str,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    if result.is_err() {
+        let _lock = ShabbyLock::lock(BINDING_NAME, &get_processed_messages_key(kind, task_id));
+        actor_kvp::del(BINDING_NAME, &get_claim_key(kind, task_id, key))?;
+    }
+    result
+}
+
+/// Duplicates are acknowledged as if they were processed, so that the sender stops retrying.
+pub fn acknowledge_duplicate(reply_to: &str, task_id: &str) -> anyhow::Result<()> {
+    debug!("ignore duplicate message of task {}", task_id);
+    response_reply_with_subject(
+        "",
+        reply_to,
+        format!("message of task {} has been processed", task_id).into_bytes(),
+    )
+}
+
+pub fn delete(kind: TaskKind, task_id: &str) -> anyhow::Result<()> {
+    let store_key = get_processed_messages_key(kind, task_id);
+    let _lock = ShabbyLock::lock(BINDING_NAME, &store_key);
+    actor_kvp::del(BINDING_NAME, &store_key)?;
+    Ok(())
+}
+
+fn get_processed_messages(kind: TaskKind, task_id: &str) -> anyhow::Result<Vec<String>> {
+    Ok(
+        actor_kvp::get::<Vec<String>>(BINDING_NAME, &get_processed_messages_key(kind, task_id))?
+            .unwrap_or_default(),
+    )
+}
+
+fn get_processed_messages_key(kind: TaskKind, task_id: &str) -> String {
+    format!("{}_{:?}_{}", PREFIX_PROCESSED_MESSAGES, kind, task_id)
+}
+
+fn get_claim_key(kind: TaskKind, task_id: &str, key: &str) -> String {
+    format!("{}_{:?}_{}_{}", PREFIX_CLAIMED_MESSAGE, kind, task_id, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_message_from_same_peer_has_same_key() {
+        assert_eq!(
+            message_key("TaskExecutionResponse", "peer", &[1, 2]),
+            message_key("TaskExecutionResponse", "peer", &[1, 2])
+        );
+        assert_ne!(
+            message_key("TaskExecutionResponse", "peer", &[1, 2]),
+            message_key("TaskExecutionResponse", "peer", &[1, 3])
+        );
+        assert_ne!(
+            message_key("TaskExecutionResponse", "peer1", &[1, 2]),
+            message_key("TaskExecutionResponse", "peer2", &[1, 2])
+        );
+        assert_ne!(
+            message_key("TaskExecutionRequest", "peer", &[1, 2]),
+            message_key("TaskExecutionResponse", "peer", &[1, 2])
+        );
+    }
+
+    #[test]
+    fn fields_are_length_prefixed() {
+        assert_ne!(message_key("ab", "c", &[]), message_key("a", "bc", &[]));
+    }
+}
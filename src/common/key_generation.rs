@@ -1,19 +1,20 @@
+use super::payment::TaskPayment;
+use super::rsa_key::{rsa_decrypt_by_task, save_rsa_private_key, wipe_rsa_private_key};
+use super::secret::SecretBuffer;
+use super::signed_message::{send_signed_message, verify_node_signature};
 use super::task_info::TaskInfo;
-use crate::BINDING_NAME;
-use anyhow::anyhow;
+use super::task_role::{TaskRole, TaskRoleRecord};
+#[cfg(feature = "legacy_candidate_signature")]
+use tea_actor_utility::actor_util::verify_ed25519_signature;
 use tea_actor_utility::{
     actor_env::get_my_ephemeral_id,
-    actor_kvp,
-    actor_util::{
-        generate_rsa_keypair, rsa_decrypt, rsa_key_to_bytes, sign_ed25519_message,
-        verify_ed25519_signature,
-    },
+    actor_util::{generate_rsa_keypair, rsa_key_to_bytes, sign_ed25519_message},
     ipfs_p2p::send_message,
-    layer1::lookup_node_profile,
 };
 use wascc_actor::HandlerResult;
 
-pub const PREFIX_KEY_GEN_RSA_KEY: &'static str = "key_gen_rsa_key";
+const CANDIDATE_SIGNATURE_TAG: &[u8] = b"gluon_key_candidate";
+const CANDIDATE_SIGNATURE_VERSION: u8 = 2;
 
 pub fn send_key_candidate_request(
     peer_id: &str,
@@ -25,8 +26,17 @@ pub fn send_key_candidate_request(
     let k = task_info.exec_info.k as u32;
     let key_type = task_info.exec_info.task_type.clone();
     let delegator_ephemeral_id = get_my_ephemeral_id().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let payment = task_info.payment;
     let signature = sign_ed25519_message(
-        &to_candidate_signature_bytes(&task_id, n, k, &key_type, &delegator_ephemeral_id, executor),
+        &to_candidate_signature_bytes(
+            &task_id,
+            n,
+            k,
+            &key_type,
+            &delegator_ephemeral_id,
+            executor,
+            &payment,
+        ),
         None,
     )?;
 
@@ -38,6 +48,8 @@ pub fn send_key_candidate_request(
         delegator_ephemeral_id,
         executor,
         signature,
+        executor_fee: payment.executor_fee,
+        initial_pinner_fee: payment.initial_pinner_fee,
     };
     debug!("begin send_key_candidate_request to {} with params: {:?}", peer_id, &req);
     send_message(
@@ -52,7 +64,7 @@ pub fn send_key_candidate_request(
 pub fn verify_to_candidate_signature<F>(
     peer_id: &str,
     req: &crate::p2p_proto::KeyGenerationCandidateRequest,
-    mut callback: F,
+    callback: F,
 ) -> anyhow::Result<()>
 where
     F: FnMut() -> HandlerResult<()> + Clone + Sync + Send + 'static,
@@ -64,32 +76,35 @@ where
         &req.key_type,
         &req.delegator_ephemeral_id,
         req.executor,
+        &TaskPayment {
+            executor_fee: req.executor_fee,
+            initial_pinner_fee: req.initial_pinner_fee,
+        },
     );
-    if !verify_ed25519_signature(
+    // delegators not upgraded yet still sign the unprefixed format
+    #[cfg(feature = "legacy_candidate_signature")]
+    let raw = match verify_ed25519_signature(
         req.delegator_ephemeral_id.clone(),
-        raw,
+        raw.clone(),
         req.signature.clone(),
     )? {
-        return Err(anyhow::anyhow!("invalid signature in candidate"));
-    }
-
-    let peer_id = peer_id.to_string();
-    lookup_node_profile(
+        true => raw,
+        false => to_legacy_candidate_signature_bytes(
+            &req.task_id,
+            req.n,
+            req.k,
+            &req.key_type,
+            &req.delegator_ephemeral_id,
+            req.executor,
+        ),
+    };
+    verify_node_signature(
+        peer_id,
         &req.delegator_ephemeral_id,
-        "actor.gluon.inbox",
-        move |profile| {
-            if !peer_id.eq(&profile.peer_id) {
-                let msg = format!(
-                    "invalid delegator: peer_id mismatch, expect is {}, actual is {}",
-                    &profile.peer_id, &peer_id
-                );
-                return Err(msg.into());
-            }
-            callback()
-        },
+        raw,
+        req.signature.clone(),
+        callback,
     )
-    .map_err(|e| anyhow!("{}", e))?;
-    Ok(())
 }
 
 fn to_candidate_signature_bytes(
@@ -99,6 +114,36 @@ fn to_candidate_signature_bytes(
     key_type: &str,
     delegator_ephemeral_id: &[u8],
     executor: bool,
+    payment: &TaskPayment,
+) -> Vec<u8> {
+    let mut buf = CANDIDATE_SIGNATURE_TAG.to_vec();
+    buf.push(CANDIDATE_SIGNATURE_VERSION);
+    buf.extend(&(task_id.len() as u32).to_le_bytes());
+    buf.extend(task_id.as_bytes());
+    buf.extend(&n.to_le_bytes());
+    buf.extend(&k.to_le_bytes());
+    buf.extend(&(key_type.len() as u32).to_le_bytes());
+    buf.extend(key_type.as_bytes());
+    buf.extend(&(delegator_ephemeral_id.len() as u32).to_le_bytes());
+    buf.extend(delegator_ephemeral_id);
+    buf.push(match executor {
+        true => 1u8,
+        false => 0u8,
+    });
+    buf.extend(&payment.executor_fee.to_le_bytes());
+    buf.extend(&payment.initial_pinner_fee.to_le_bytes());
+    buf
+}
+
+/// Format before the tag and length prefixes were added, different requests may share it.
+#[cfg(feature = "legacy_candidate_signature")]
+fn to_legacy_candidate_signature_bytes(
+    task_id: &str,
+    n: u32,
+    k: u32,
+    key_type: &str,
+    delegator_ephemeral_id: &[u8],
+    executor: bool,
 ) -> Vec<u8> {
     let mut buf = task_id.as_bytes().to_vec();
     buf.extend(&n.to_le_bytes());
@@ -112,27 +157,23 @@ fn to_candidate_signature_bytes(
     buf
 }
 
-pub fn send_key_generation_request(
-    peer_id: &str,
-    task_info: &TaskInfo,
-    apply_executor: bool,
-) -> anyhow::Result<()> {
+pub fn send_key_generation_request(peer_id: &str, record: &TaskRoleRecord) -> anyhow::Result<()> {
+    let task_info = &record.task_info;
     let rsa_key_pkcs1 = generate_rsa_keypair()?;
-    actor_kvp::set(
-        BINDING_NAME,
-        &get_rsa_encrypt_key(&task_info.task_id),
+    save_rsa_private_key(
+        record.rsa_key_prefix(),
+        &task_info.task_id,
         &rsa_key_pkcs1.private_key,
-        6000,
     )?;
 
     let req = crate::p2p_proto::TaskKeyGenerationApplyRequst {
         task_id: task_info.task_id.clone(),
         rsa_pub_key: rsa_key_to_bytes(rsa_key_pkcs1.public_key)?,
         cap_desc: None,
-        apply_executor,
+        apply_executor: record.applied == TaskRole::Executor,
     };
 
-    send_message(
+    send_signed_message(
         peer_id,
         &task_info.task_id,
         crate::p2p_proto::GeneralMsg {
@@ -141,19 +182,92 @@ pub fn send_key_generation_request(
     )
 }
 
-pub fn decrypt_key_slice(task_id: &str, key_slice_encrypted: Vec<u8>) -> anyhow::Result<Vec<u8>> {
-    let rsa_priv_key: String =
-        actor_kvp::get(BINDING_NAME, &get_rsa_encrypt_key(task_id))?.ok_or(anyhow!(
-            "{}:{} could not find rsa pub key {} corresponding rsa private",
-            line!(),
-            file!(),
-            task_id
-        ))?;
-
-    let key_slice = rsa_decrypt(rsa_key_to_bytes(rsa_priv_key)?, key_slice_encrypted)?;
-    Ok(key_slice)
+pub fn decrypt_key_slice(
+    task_id: &str,
+    key_slice_encrypted: Vec<u8>,
+) -> anyhow::Result<SecretBuffer> {
+    let record = TaskRoleRecord::get(task_id)?;
+    rsa_decrypt_by_task(record.rsa_key_prefix(), task_id, key_slice_encrypted)
+}
+
+pub fn wipe_key_generation_rsa_key(task_id: &str) -> anyhow::Result<()> {
+    let record = TaskRoleRecord::get(task_id)?;
+    wipe_rsa_private_key(record.rsa_key_prefix(), task_id)
 }
 
-fn get_rsa_encrypt_key(task_id: &str) -> String {
-    format!("{}_{}", PREFIX_KEY_GEN_RSA_KEY, task_id)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const PAID: TaskPayment = TaskPayment {
+        executor_fee: 100,
+        initial_pinner_fee: 10,
+    };
+
+    #[test]
+    fn boundaries_of_text_fields_are_kept() {
+        assert_ne!(
+            to_candidate_signature_bytes("ab", 3, 2, "c", &[1], true, &PAID),
+            to_candidate_signature_bytes("a", 3, 2, "bc", &[1], true, &PAID)
+        );
+        assert_ne!(
+            to_candidate_signature_bytes("a", 3, 2, "b", &[1, 2], true, &PAID),
+            to_candidate_signature_bytes("a", 3, 2, "b", &[1], true, &PAID)
+        );
+        assert!(
+            to_candidate_signature_bytes("a", 3, 2, "b", &[1], true, &PAID)
+                .starts_with(CANDIDATE_SIGNATURE_TAG)
+        );
+    }
+
+    #[test]
+    fn distinct_requests_never_collide() {
+        let texts = ["", "a", "b", "ab", "ba", "aab"];
+        let ids: [&[u8]; 4] = [&[], &[1], &[1, 1], &[0, 0, 0, 0]];
+        let numbers = [0u32, 1, 256, u32::MAX];
+        let payments = [
+            TaskPayment::default(),
+            PAID,
+            TaskPayment {
+                executor_fee: 10,
+                initial_pinner_fee: 100,
+            },
+        ];
+        let mut requests = 0;
+        let mut encoded = HashSet::new();
+        for task_id in texts.iter() {
+            for key_type in texts.iter() {
+                for id in ids.iter() {
+                    for n in numbers.iter() {
+                        for k in numbers.iter() {
+                            for executor in [true, false].iter() {
+                                for payment in payments.iter() {
+                                    requests += 1;
+                                    encoded.insert(to_candidate_signature_bytes(
+                                        task_id, *n, *k, key_type, id, *executor, payment,
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(requests, encoded.len());
+    }
+
+    #[cfg(feature = "legacy_candidate_signature")]
+    #[test]
+    fn legacy_format_is_not_accepted_as_new_format() {
+        let legacy = to_legacy_candidate_signature_bytes("ab", 3, 2, "c", &[1], true);
+        assert_eq!(
+            legacy,
+            to_legacy_candidate_signature_bytes("a", 3, 2, "bc", &[1], true)
+        );
+        assert_ne!(
+            legacy,
+            to_candidate_signature_bytes("ab", 3, 2, "c", &[1], true, &PAID)
+        );
+    }
 }
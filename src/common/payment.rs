@@ -0,0 +1,155 @@
+use super::task_info::TaskInfo;
+use super::task_role::TaskRole;
+use crate::BINDING_NAME;
+use tea_actor_utility::actor_kvp;
+
+const PREFIX_MINIMUM_FEE: &'static str = "gluon_minimum_fee";
+
+/// Fees a task pays to each of its roles.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskPayment {
+    pub executor_fee: u64,
+    pub initial_pinner_fee: u64,
+}
+
+impl TaskPayment {
+    pub fn fee_of(&self, role: TaskRole) -> u64 {
+        match role {
+            TaskRole::Executor => self.executor_fee,
+            TaskRole::InitialPinner => self.initial_pinner_fee,
+        }
+    }
+}
+
+impl From<&crate::actor_delegate_proto::TaskPaymentDescription> for TaskPayment {
+    fn from(value: &crate::actor_delegate_proto::TaskPaymentDescription) -> Self {
+        TaskPayment {
+            executor_fee: value.executor_fee,
+            initial_pinner_fee: value.initial_pinner_fee,
+        }
+    }
+}
+
+/// KV key to configure the minimum fee (u64) I accept to be `role` of tasks of `key_type`.
+/// If `key_type` is `None` the key configures the minimum fee of key types not configured.
+pub fn minimum_fee_key(role: TaskRole, key_type: Option<&str>) -> String {
+    match key_type {
+        Some(key_type) => format!("{}_{:?}_{}", PREFIX_MINIMUM_FEE, role, key_type),
+        None => format!("{}_{:?}", PREFIX_MINIMUM_FEE, role),
+    }
+}
+
+/// Minimum fee I accept to be `role` of tasks of `key_type`, tasks are free to run if nothing
+/// is configured.
+pub fn minimum_fee(role: TaskRole, key_type: &str) -> anyhow::Result<u64> {
+    let fee = actor_kvp::get::<u64>(BINDING_NAME, &minimum_fee_key(role, Some(key_type)))?;
+    match fee {
+        Some(fee) => Ok(fee),
+        None => Ok(actor_kvp::get::<u64>(BINDING_NAME, &minimum_fee_key(role, None))?.unwrap_or(0)),
+    }
+}
+
+/// Whether the task pays enough for all `roles` I may take by answering its candidate request.
+pub fn willing_to_run(task_info: &TaskInfo, roles: &[TaskRole]) -> anyhow::Result<bool> {
+    pays_enough(&task_info.payment, roles, |role| {
+        minimum_fee(role, &task_info.exec_info.task_type)
+    })
+}
+
+fn pays_enough<F>(payment: &TaskPayment, roles: &[TaskRole], minimum_fee: F) -> anyhow::Result<bool>
+where
+    F: Fn(TaskRole) -> anyhow::Result<u64>,
+{
+    for role in roles {
+        let minimum = minimum_fee(*role)?;
+        if payment.fee_of(*role) < minimum {
+            info!(
+                "fee of {:?} is {}, less than the minimum {}",
+                role,
+                payment.fee_of(*role),
+                minimum
+            );
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn minimum(role: TaskRole) -> anyhow::Result<u64> {
+        Ok(match role {
+            TaskRole::Executor => 100,
+            TaskRole::InitialPinner => 10,
+        })
+    }
+
+    #[test]
+    fn decline_if_any_role_pays_too_little() -> anyhow::Result<()> {
+        let payment = TaskPayment {
+            executor_fee: 100,
+            initial_pinner_fee: 9,
+        };
+        assert!(pays_enough(&payment, &[TaskRole::Executor], minimum)?);
+        assert!(!pays_enough(&payment, &[TaskRole::InitialPinner], minimum)?);
+        assert!(!pays_enough(
+            &payment,
+            &TaskRole::agreed_roles(true),
+            minimum
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn free_task_is_accepted_without_minimum() -> anyhow::Result<()> {
+        assert!(pays_enough(
+            &TaskPayment::default(),
+            &TaskRole::agreed_roles(true),
+            |_| Ok(0)
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn reshare_pays_like_key_generation() -> anyhow::Result<()> {
+        let mut res = crate::actor_delegate_proto::ReshareResponse::default();
+        res.task_id = vec![1, 2];
+        res.data_adhoc.n = 3;
+        res.data_adhoc.k = 2;
+        res.payment = crate::actor_delegate_proto::TaskPaymentDescription {
+            executor_fee: 100,
+            initial_pinner_fee: 10,
+        };
+        let task_info = TaskInfo::try_from(res.clone())?;
+        assert!(pays_enough(
+            &task_info.payment,
+            &TaskRole::agreed_roles(true),
+            minimum
+        )?);
+
+        res.payment.initial_pinner_fee = 9;
+        let task_info = TaskInfo::try_from(res)?;
+        assert!(!pays_enough(
+            &task_info.payment,
+            &TaskRole::agreed_roles(false),
+            minimum
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn minimum_fee_keys_are_distinct() {
+        assert_ne!(
+            minimum_fee_key(TaskRole::Executor, Some("bitcoin_mainnet")),
+            minimum_fee_key(TaskRole::InitialPinner, Some("bitcoin_mainnet"))
+        );
+        assert_ne!(
+            minimum_fee_key(TaskRole::Executor, None),
+            minimum_fee_key(TaskRole::Executor, Some(""))
+        );
+    }
+}
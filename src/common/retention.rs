@@ -0,0 +1,317 @@
+use super::utils::current_timestamp;
+use crate::BINDING_NAME;
+use tea_actor_utility::actor_kvp;
+use tea_actor_utility::actor_kvp::ShabbyLock;
+
+const FINISHED_TASKS: &'static str = "gluon_finished_tasks";
+const UNFINISHED_TASKS: &'static str = "gluon_unfinished_tasks";
+/// State recorded in the summary of a task archived without reaching a terminal state
+pub const EXPIRED_STATE: &'static str = "Expired";
+const PREFIX_TASK_SUMMARY: &'static str = "gluon_task_summary";
+const PREFIX_TASK_TIMESTAMPS: &'static str = "gluon_task_timestamps";
+/// KV key to configure how long (in seconds) a finished task is kept before it is archived
+pub const TASK_RETENTION_SECONDS_KEY: &'static str = "gluon_task_retention_seconds";
+const DEFAULT_TASK_RETENTION_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TaskKind {
+    DelegatorKeyGen,
+    DelegatorSign,
+    Executor,
+    InitialPinner,
+}
+
+/// Compact record of a task in terminal state, it is kept after the full store item is deleted.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSummary {
+    pub kind: TaskKind,
+    pub task_id: String,
+    pub state: String,
+    pub finished_at: u64,
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+impl TaskSummary {
+    pub fn is_expired(&self, now: u64, retention_seconds: u64) -> bool {
+        now >= self.finished_at.saturating_add(retention_seconds)
+    }
+
+    /// Summary of a task whose store item has been archived
+    pub fn get(kind: TaskKind, task_id: &str) -> anyhow::Result<Option<Self>> {
+        Ok(actor_kvp::get::<TaskSummary>(
+            BINDING_NAME,
+            &get_task_summary_key(kind, task_id),
+        )?)
+    }
+}
+
+/// Unix timestamps (in seconds) of a store item, kept beside it so that store items do not
+/// need to carry them.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskTimestamps {
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl TaskTimestamps {
+    /// A task not updated for the retention period will never finish, e.g. a candidate that
+    /// was not elected.
+    pub fn is_stale(&self, now: u64, retention_seconds: u64) -> bool {
+        now >= self.updated_at.saturating_add(retention_seconds)
+    }
+
+    pub fn get(kind: TaskKind, task_id: &str) -> anyhow::Result<Option<Self>> {
+        Ok(actor_kvp::get::<TaskTimestamps>(
+            BINDING_NAME,
+            &get_task_timestamps_key(kind, task_id),
+        )?)
+    }
+}
+
+/// Called every time a store item is saved.
+pub fn touch(kind: TaskKind, task_id: &str) -> anyhow::Result<()> {
+    let now = current_timestamp()?;
+    let key = get_task_timestamps_key(kind, task_id);
+    let _lock = ShabbyLock::lock(BINDING_NAME, &key);
+    let timestamps = match actor_kvp::get::<TaskTimestamps>(BINDING_NAME, &key)? {
+        Some(v) => TaskTimestamps {
+            created_at: v.created_at,
+            updated_at: now,
+        },
+        None => {
+            update_unfinished_tasks(|tasks| tasks.push((kind, task_id.to_string())))?;
+            TaskTimestamps {
+                created_at: now,
+                updated_at: now,
+            }
+        }
+    };
+    actor_kvp::set_forever(BINDING_NAME, &key, &timestamps)?;
+    Ok(())
+}
+
+/// Called when a store item is saved in terminal state, saving it again keeps the first
+/// finish time.
+pub fn mark_finished<S>(kind: TaskKind, task_id: &str, state: &S) -> anyhow::Result<()>
+where
+    S: std::fmt::Debug,
+{
+    let finished_at = current_timestamp()?;
+    let created_at = TaskTimestamps::get(kind, task_id)?
+        .map(|v| v.created_at)
+        .unwrap_or(finished_at);
+    update_unfinished_tasks(|tasks| tasks.retain(|v| !(v.0 == kind && v.1 == task_id)))?;
+    update_finished_tasks(|tasks| {
+        if !tasks.iter().any(|v| v.kind == kind && v.task_id == task_id) {
+            tasks.push(TaskSummary {
+                kind,
+                task_id: task_id.to_string(),
+                state: format!("{:?}", state),
+                finished_at,
+                created_at,
+            });
+        }
+    })
+}
+
+/// Finish time of a task that is in terminal state but not archived yet
+pub fn finished_at(kind: TaskKind, task_id: &str) -> anyhow::Result<Option<u64>> {
+    let _lock = ShabbyLock::lock(BINDING_NAME, FINISHED_TASKS);
+    Ok(
+        actor_kvp::get::<Vec<TaskSummary>>(BINDING_NAME, FINISHED_TASKS)?
+            .unwrap_or_default()
+            .iter()
+            .find(|v| v.kind == kind && v.task_id == task_id)
+            .map(|v| v.finished_at),
+    )
+}
+
+pub fn retention_seconds() -> anyhow::Result<u64> {
+    Ok(
+        actor_kvp::get::<u64>(BINDING_NAME, TASK_RETENTION_SECONDS_KEY)?
+            .unwrap_or(DEFAULT_TASK_RETENTION_SECONDS),
+    )
+}
+
+/// Archives finished tasks older than the retention period, and archives tasks not updated for
+/// the retention period as [`EXPIRED_STATE`]. `delete` removes the full store item of a task.
+/// Returns number of archived tasks.
+pub fn sweep_finished_tasks<F>(now: u64, delete: F) -> anyhow::Result<usize>
+where
+    F: Fn(&TaskSummary) -> anyhow::Result<()>,
+{
+    let retention_seconds = retention_seconds()?;
+    let finished_tasks = {
+        let _lock = ShabbyLock::lock(BINDING_NAME, FINISHED_TASKS);
+        actor_kvp::get::<Vec<TaskSummary>>(BINDING_NAME, FINISHED_TASKS)?.unwrap_or_default()
+    };
+    let (expired, _) = split_expired(finished_tasks, now, retention_seconds);
+
+    let mut archived = 0;
+    for summary in expired {
+        if let Err(e) = archive(&summary, &delete) {
+            error!(
+                "failed to archive {:?} task {}, details: {}",
+                summary.kind, &summary.task_id, e
+            );
+            continue;
+        }
+        archived += 1;
+    }
+    Ok(archived + expire_stale_tasks(now, retention_seconds, &delete)?)
+}
+
+fn expire_stale_tasks<F>(now: u64, retention_seconds: u64, delete: &F) -> anyhow::Result<usize>
+where
+    F: Fn(&TaskSummary) -> anyhow::Result<()>,
+{
+    let unfinished_tasks = {
+        let _lock = ShabbyLock::lock(BINDING_NAME, UNFINISHED_TASKS);
+        actor_kvp::get::<Vec<(TaskKind, String)>>(BINDING_NAME, UNFINISHED_TASKS)?
+            .unwrap_or_default()
+    };
+
+    let mut expired = 0;
+    for (kind, task_id) in unfinished_tasks {
+        let timestamps = match TaskTimestamps::get(kind, &task_id)? {
+            Some(v) if v.is_stale(now, retention_seconds) => v,
+            _ => continue,
+        };
+        let summary = TaskSummary {
+            kind,
+            task_id,
+            state: EXPIRED_STATE.to_string(),
+            finished_at: now,
+            created_at: timestamps.created_at,
+        };
+        info!("{:?} task {} is stale, archive it", kind, &summary.task_id);
+        if let Err(e) = archive(&summary, delete) {
+            error!(
+                "failed to archive {:?} task {}, details: {}",
+                summary.kind, &summary.task_id, e
+            );
+            continue;
+        }
+        expired += 1;
+    }
+    Ok(expired)
+}
+
+fn archive<F>(summary: &TaskSummary, delete: &F) -> anyhow::Result<()>
+where
+    F: Fn(&TaskSummary) -> anyhow::Result<()>,
+{
+    let mut archived = summary.clone();
+    if let Some(timestamps) = TaskTimestamps::get(summary.kind, &summary.task_id)? {
+        archived.created_at = timestamps.created_at;
+    }
+    actor_kvp::set_forever(
+        BINDING_NAME,
+        &get_task_summary_key(summary.kind, &summary.task_id),
+        &archived,
+    )?;
+    delete(summary)?;
+    actor_kvp::del(
+        BINDING_NAME,
+        &get_task_timestamps_key(summary.kind, &summary.task_id),
+    )?;
+    update_unfinished_tasks(|tasks| {
+        tasks.retain(|v| !(v.0 == summary.kind && v.1 == summary.task_id))
+    })?;
+    update_finished_tasks(|tasks| {
+        tasks.retain(|v| !(v.kind == summary.kind && v.task_id == summary.task_id))
+    })
+}
+
+fn split_expired(
+    tasks: Vec<TaskSummary>,
+    now: u64,
+    retention_seconds: u64,
+) -> (Vec<TaskSummary>, Vec<TaskSummary>) {
+    tasks
+        .into_iter()
+        .partition(|v| v.is_expired(now, retention_seconds))
+}
+
+fn update_finished_tasks<F>(update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<TaskSummary>),
+{
+    let _lock = ShabbyLock::lock(BINDING_NAME, FINISHED_TASKS);
+    let mut tasks =
+        actor_kvp::get::<Vec<TaskSummary>>(BINDING_NAME, FINISHED_TASKS)?.unwrap_or_default();
+    update(&mut tasks);
+    actor_kvp::set_forever(BINDING_NAME, FINISHED_TASKS, &tasks)?;
+    Ok(())
+}
+
+fn update_unfinished_tasks<F>(update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<(TaskKind, String)>),
+{
+    let _lock = ShabbyLock::lock(BINDING_NAME, UNFINISHED_TASKS);
+    let mut tasks = actor_kvp::get::<Vec<(TaskKind, String)>>(BINDING_NAME, UNFINISHED_TASKS)?
+        .unwrap_or_default();
+    update(&mut tasks);
+    actor_kvp::set_forever(BINDING_NAME, UNFINISHED_TASKS, &tasks)?;
+    Ok(())
+}
+
+fn get_task_summary_key(kind: TaskKind, task_id: &str) -> String {
+    format!("{}_{:?}_{}", PREFIX_TASK_SUMMARY, kind, task_id)
+}
+
+fn get_task_timestamps_key(kind: TaskKind, task_id: &str) -> String {
+    format!("{}_{:?}_{}", PREFIX_TASK_TIMESTAMPS, kind, task_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(task_id: &str, finished_at: u64) -> TaskSummary {
+        TaskSummary {
+            kind: TaskKind::Executor,
+            task_id: task_id.into(),
+            state: "Executed".into(),
+            finished_at,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn expire_after_retention_period() {
+        let item = summary("task", 100);
+        assert!(!item.is_expired(109, 10));
+        assert!(item.is_expired(110, 10));
+        assert!(!summary("task", u64::MAX).is_expired(u64::MAX - 1, 10));
+    }
+
+    #[test]
+    fn stale_after_retention_period_without_update() {
+        let timestamps = TaskTimestamps {
+            created_at: 10,
+            updated_at: 100,
+        };
+        assert!(!timestamps.is_stale(109, 10));
+        assert!(timestamps.is_stale(110, 10));
+    }
+
+    #[test]
+    fn split_expired_tasks() {
+        let tasks = vec![summary("t1", 10), summary("t2", 50), summary("t3", 20)];
+        let (expired, kept) = split_expired(tasks, 30, 10);
+        assert_eq!(
+            vec!["t1", "t3"],
+            expired
+                .iter()
+                .map(|v| v.task_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, kept.len());
+        assert_eq!("t2", kept[0].task_id);
+    }
+}
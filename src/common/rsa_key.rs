@@ -0,0 +1,39 @@
+use super::error::TaskError;
+use super::secret::SecretBuffer;
+use crate::BINDING_NAME;
+use tea_actor_utility::{
+    actor_kvp,
+    actor_util::{rsa_decrypt, rsa_key_to_bytes},
+};
+
+/// RSA private keys of a task are kept until the task reaches a terminal state, then
+/// [`wipe_rsa_private_key`] should be called to remove them.
+pub fn save_rsa_private_key(prefix: &str, task_id: &str, private_key: &str) -> anyhow::Result<()> {
+    actor_kvp::set_forever(
+        BINDING_NAME,
+        &get_rsa_key_store_key(prefix, task_id),
+        &private_key.to_string(),
+    )?;
+    Ok(())
+}
+
+pub fn rsa_decrypt_by_task(
+    prefix: &str,
+    task_id: &str,
+    encrypted: Vec<u8>,
+) -> anyhow::Result<SecretBuffer> {
+    let rsa_priv_key: String =
+        actor_kvp::get(BINDING_NAME, &get_rsa_key_store_key(prefix, task_id))?
+            .ok_or(TaskError::TaskExpired(task_id.to_string()))?;
+    let rsa_priv_key = SecretBuffer::from(rsa_key_to_bytes(rsa_priv_key)?);
+    Ok(rsa_decrypt(rsa_priv_key.to_vec(), encrypted)?.into())
+}
+
+pub fn wipe_rsa_private_key(prefix: &str, task_id: &str) -> anyhow::Result<()> {
+    actor_kvp::del(BINDING_NAME, &get_rsa_key_store_key(prefix, task_id))?;
+    Ok(())
+}
+
+fn get_rsa_key_store_key(prefix: &str, task_id: &str) -> String {
+    format!("{}_{}", prefix, task_id)
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::ops::Deref;
+use zeroize::Zeroize;
+
+/// Buffer of private key material (private keys, Shamir shares) that is wiped when dropped.
+///
+/// Shamir and signing host calls borrow the secret through [`SecretBuffer::expose`] (or
+/// [`expose_all`]), so no unprotected copy of it is made in the actor. AES and RSA host calls
+/// still take ownership of their input, they get a [`SecretBuffer::to_vec`] copy that the actor
+/// can not wipe.
+pub struct SecretBuffer(Vec<u8>);
+
+impl SecretBuffer {
+    pub fn new(data: Vec<u8>) -> Self {
+        SecretBuffer(data)
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Unprotected copy for host calls that take ownership of their input, the copy can not be
+    /// wiped after the call. Prefer [`SecretBuffer::expose`] for host calls that borrow.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+impl From<Vec<u8>> for SecretBuffer {
+    fn from(data: Vec<u8>) -> Self {
+        SecretBuffer::new(data)
+    }
+}
+
+impl Deref for SecretBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.expose()
+    }
+}
+
+impl Zeroize for SecretBuffer {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBuffer([REDACTED; {}])", self.0.len())
+    }
+}
+
+pub fn to_secret_buffers(items: Vec<Vec<u8>>) -> Vec<SecretBuffer> {
+    items.into_iter().map(SecretBuffer::from).collect()
+}
+
+pub fn expose_all(items: &[SecretBuffer]) -> Vec<&[u8]> {
+    items.iter().map(|v| v.expose()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_does_not_leak_secret() {
+        let secret = SecretBuffer::from(vec![0xab; 4]);
+        let output = format!("{:?}", secret);
+        assert_eq!("SecretBuffer([REDACTED; 4])", output);
+        assert!(!output.contains("171"));
+    }
+
+    #[test]
+    fn zeroize_wipes_buffer() {
+        let mut secret = SecretBuffer::from(vec![1, 2, 3]);
+        assert_eq!(&[1, 2, 3], secret.expose());
+        secret.zeroize();
+        assert!(secret.is_empty());
+    }
+
+    #[test]
+    fn conversion_keeps_content() {
+        let secrets = to_secret_buffers(vec![vec![1], vec![2, 3]]);
+        assert_eq!(vec![&[1][..], &[2, 3][..]], expose_all(&secrets));
+    }
+}
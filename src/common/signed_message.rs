@@ -0,0 +1,129 @@
+use anyhow::anyhow;
+use prost::Message;
+use tea_actor_utility::{
+    actor_env::get_my_ephemeral_id,
+    actor_util::{sign_ed25519_message, verify_ed25519_signature},
+    encode_protobuf,
+    ipfs_p2p::send_message,
+    layer1::lookup_node_profile,
+};
+use wascc_actor::HandlerResult;
+
+const SIGNED_MESSAGE_TAG: &[u8] = b"gluon_signed_message";
+const SIGNED_MESSAGE_VERSION: u8 = 1;
+
+/// Sends `msg` wrapped in a `SignedMessage` envelope signed with my ephemeral key.
+pub fn send_signed_message(
+    peer_id: &str,
+    task_id: &str,
+    msg: crate::p2p_proto::GeneralMsg,
+) -> anyhow::Result<()> {
+    let ephemeral_id = get_my_ephemeral_id().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let payload = encode_protobuf(msg)?;
+    let signature = sign_ed25519_message(&to_signed_message_bytes(&ephemeral_id, &payload), None)?;
+    send_message(
+        peer_id,
+        task_id,
+        crate::p2p_proto::GeneralMsg {
+            msg: Some(crate::p2p_proto::general_msg::Msg::SignedMessage(
+                crate::p2p_proto::SignedMessage {
+                    ephemeral_id,
+                    signature,
+                    payload,
+                },
+            )),
+        },
+    )
+}
+
+/// Verifies the envelope sent by the node of `peer_id`, `callback` gets the wrapped message.
+pub fn verify_signed_message<F>(
+    peer_id: &str,
+    signed: &crate::p2p_proto::SignedMessage,
+    mut callback: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(crate::p2p_proto::GeneralMsg) -> HandlerResult<()> + Clone + Sync + Send + 'static,
+{
+    let msg = crate::p2p_proto::GeneralMsg::decode(signed.payload.as_slice())?;
+    verify_node_signature(
+        peer_id,
+        &signed.ephemeral_id,
+        to_signed_message_bytes(&signed.ephemeral_id, &signed.payload),
+        signed.signature.clone(),
+        move || callback(msg.clone()),
+    )
+}
+
+/// Verifies `signature` of `raw` made with `ephemeral_id`, and that the signer is the node of
+/// `peer_id` who sent the message.
+pub fn verify_node_signature<F>(
+    peer_id: &str,
+    ephemeral_id: &[u8],
+    raw: Vec<u8>,
+    signature: Vec<u8>,
+    mut callback: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> HandlerResult<()> + Clone + Sync + Send + 'static,
+{
+    if !verify_ed25519_signature(ephemeral_id.to_vec(), raw, signature)? {
+        return Err(anyhow!("invalid signature of peer {}", peer_id));
+    }
+
+    let peer_id = peer_id.to_string();
+    lookup_node_profile(ephemeral_id, "actor.gluon.inbox", move |profile| {
+        if !peer_id.eq(&profile.peer_id) {
+            let msg = format!(
+                "invalid signer: peer_id mismatch, expect is {}, actual is {}",
+                &profile.peer_id, &peer_id
+            );
+            return Err(msg.into());
+        }
+        callback()
+    })
+    .map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+fn to_signed_message_bytes(ephemeral_id: &[u8], payload: &[u8]) -> Vec<u8> {
+    to_signature_bytes(
+        SIGNED_MESSAGE_TAG,
+        SIGNED_MESSAGE_VERSION,
+        &[ephemeral_id, payload],
+    )
+}
+
+/// Bytes to sign for a message: its tag and format version followed by each field prefixed with
+/// its length, so that no two messages or field splits share the same bytes.
+pub fn to_signature_bytes(tag: &[u8], version: u8, fields: &[&[u8]]) -> Vec<u8> {
+    let mut buf = tag.to_vec();
+    buf.push(version);
+    for field in fields {
+        buf.extend(&(field.len() as u32).to_le_bytes());
+        buf.extend(*field);
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_bytes_have_no_ambiguity() {
+        assert_ne!(
+            to_signed_message_bytes(&[1, 2], &[3]),
+            to_signed_message_bytes(&[1], &[2, 3])
+        );
+        assert!(to_signed_message_bytes(&[1], &[2]).starts_with(SIGNED_MESSAGE_TAG));
+    }
+
+    #[test]
+    fn version_is_signed() {
+        assert_ne!(
+            to_signature_bytes(b"tag", 1, &[&[1]]),
+            to_signature_bytes(b"tag", 2, &[&[1]])
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::Debug;
+
+/// State of a store item, it only moves along its transition table.
+pub trait StateMachine: Debug + Clone + PartialEq {
+    type Event: Debug + Clone;
+
+    /// Returns the state `event` leads to, or `None` if `event` is illegal in this state.
+    fn next(&self, event: &Self::Event) -> Option<Self>;
+}
+
+/// One entry of the transition log kept in a store item.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition<S, E> {
+    pub from: S,
+    pub to: S,
+    pub event: E,
+    /// Peer whose message caused the transition, `None` for local or layer1 events
+    pub peer_id: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Moves `state` by `event` and appends the step to `log`, nothing changes if `event` is
+/// illegal in current state.
+pub fn transit<S>(
+    task_id: &str,
+    state: &mut S,
+    log: &mut Vec<Transition<S, S::Event>>,
+    event: S::Event,
+    peer_id: Option<&str>,
+    timestamp: u64,
+) -> anyhow::Result<()>
+where
+    S: StateMachine,
+{
+    let to = state.next(&event).ok_or(anyhow::anyhow!(
+        "{}:{} task {} can not handle {:?} in state {:?}",
+        line!(),
+        file!(),
+        task_id,
+        event,
+        state
+    ))?;
+    log.push(Transition {
+        from: state.clone(),
+        to: to.clone(),
+        event,
+        peer_id: peer_id.map(|v| v.to_string()),
+        timestamp,
+    });
+    *state = to;
+    Ok(())
+}
+
+/// Replays `log` from `initial` through the transition table and returns the final state.
+#[cfg(test)]
+pub fn replay<S>(initial: S, log: &[Transition<S, S::Event>]) -> anyhow::Result<S>
+where
+    S: StateMachine,
+{
+    let mut state = initial;
+    for (i, step) in log.iter().enumerate() {
+        if step.from != state || state.next(&step.event).as_ref() != Some(&step.to) {
+            return Err(anyhow::anyhow!(
+                "{}:{} step {} of transition log is not allowed: {:?}",
+                line!(),
+                file!(),
+                i,
+                step
+            ));
+        }
+        state = step.to.clone();
+    }
+    Ok(state)
+}
+
+/// Checks `next` of every state in `states` on every event in `events` against `table`, the legal
+/// transitions of the state machine. A pair missing from `table` must be illegal.
+#[cfg(test)]
+pub fn check_transition_table<S>(states: &[S], events: &[S::Event], table: &[(S, S::Event, S)])
+where
+    S: StateMachine,
+    S::Event: PartialEq,
+{
+    for from in states.iter() {
+        for event in events.iter() {
+            let expected = table
+                .iter()
+                .find(|(f, e, _)| f == from && e == event)
+                .map(|(_, _, to)| to.clone());
+            assert_eq!(
+                expected,
+                from.next(event),
+                "{:?} in state {:?}",
+                event,
+                from
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Light {
+        Off,
+        On,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Switch {
+        TurnOn,
+        TurnOff,
+    }
+
+    impl StateMachine for Light {
+        type Event = Switch;
+
+        fn next(&self, event: &Switch) -> Option<Self> {
+            match (self, event) {
+                (Light::Off, Switch::TurnOn) => Some(Light::On),
+                (Light::On, Switch::TurnOff) => Some(Light::Off),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        check_transition_table(
+            &[Light::Off, Light::On],
+            &[Switch::TurnOn, Switch::TurnOff],
+            &[
+                (Light::Off, Switch::TurnOn, Light::On),
+                (Light::On, Switch::TurnOff, Light::Off),
+            ],
+        );
+    }
+
+    #[test]
+    fn illegal_event_changes_nothing() {
+        let mut state = Light::Off;
+        let mut log = Vec::new();
+        assert!(transit("task", &mut state, &mut log, Switch::TurnOff, None, 1).is_err());
+        assert_eq!(Light::Off, state);
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn log_can_be_replayed() -> anyhow::Result<()> {
+        let mut state = Light::Off;
+        let mut log = Vec::new();
+        transit(
+            "task",
+            &mut state,
+            &mut log,
+            Switch::TurnOn,
+            Some("peer"),
+            1,
+        )?;
+        transit("task", &mut state, &mut log, Switch::TurnOff, None, 2)?;
+        assert_eq!(2, log.len());
+        assert_eq!(Some("peer".to_string()), log[0].peer_id);
+        assert_eq!(state, replay(Light::Off, &log)?);
+
+        log[1].to = Light::On;
+        assert!(replay(Light::Off, &log).is_err());
+        Ok(())
+    }
+}
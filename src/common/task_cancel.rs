@@ -0,0 +1,85 @@
+use super::signed_message::{to_signature_bytes, verify_node_signature};
+use tea_actor_utility::{
+    actor_env::get_my_ephemeral_id, actor_util::sign_ed25519_message, ipfs_p2p::send_message,
+};
+use wascc_actor::HandlerResult;
+
+const CANCEL_SIGNATURE_TAG: &[u8] = b"gluon_task_cancel";
+const CANCEL_SIGNATURE_VERSION: u8 = 1;
+
+pub fn send_task_cancel_request(peer_id: &str, task_id: &str, reason: &str) -> anyhow::Result<()> {
+    let delegator_ephemeral_id = get_my_ephemeral_id().map_err(|e| anyhow::anyhow!("{}", e))?;
+    let signature = sign_ed25519_message(
+        &to_cancel_signature_bytes(task_id, reason, &delegator_ephemeral_id),
+        None,
+    )?;
+
+    debug!(
+        "begin to send task cancel request of {} to {}",
+        task_id, peer_id
+    );
+    send_message(
+        peer_id,
+        task_id,
+        crate::p2p_proto::GeneralMsg {
+            msg: Some(crate::p2p_proto::general_msg::Msg::TaskCancelRequest(
+                crate::p2p_proto::TaskCancelRequest {
+                    task_id: task_id.to_string(),
+                    reason: reason.to_string(),
+                    delegator_ephemeral_id,
+                    signature,
+                },
+            )),
+        },
+    )
+}
+
+pub fn verify_task_cancel_request<F>(
+    peer_id: &str,
+    req: &crate::p2p_proto::TaskCancelRequest,
+    callback: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> HandlerResult<()> + Clone + Sync + Send + 'static,
+{
+    verify_node_signature(
+        peer_id,
+        &req.delegator_ephemeral_id,
+        to_cancel_signature_bytes(&req.task_id, &req.reason, &req.delegator_ephemeral_id),
+        req.signature.clone(),
+        callback,
+    )
+}
+
+fn to_cancel_signature_bytes(
+    task_id: &str,
+    reason: &str,
+    delegator_ephemeral_id: &[u8],
+) -> Vec<u8> {
+    to_signature_bytes(
+        CANCEL_SIGNATURE_TAG,
+        CANCEL_SIGNATURE_VERSION,
+        &[
+            task_id.as_bytes(),
+            reason.as_bytes(),
+            delegator_ephemeral_id,
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_bytes_have_no_ambiguity() {
+        assert_ne!(
+            to_cancel_signature_bytes("ab", "c", &[1]),
+            to_cancel_signature_bytes("a", "bc", &[1])
+        );
+        assert_ne!(
+            to_cancel_signature_bytes("a", "b", &[1]),
+            to_cancel_signature_bytes("a", "b", &[2])
+        );
+    }
+}
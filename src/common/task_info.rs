@@ -1,4 +1,5 @@
-use super::execution_info::ExecutionInfo;
+use super::execution_info::{validate_threshold, ExecutionInfo, SignMode};
+use super::payment::TaskPayment;
 use serde::export::TryFrom;
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -6,6 +7,8 @@ use serde::export::TryFrom;
 pub struct TaskInfo {
     pub task_id: String,
     pub exec_info: ExecutionInfo,
+    #[serde(default)]
+    pub payment: TaskPayment,
 }
 
 impl TryFrom<crate::p2p_proto::KeyGenerationCandidateRequest> for TaskInfo {
@@ -20,6 +23,11 @@ impl TryFrom<crate::p2p_proto::KeyGenerationCandidateRequest> for TaskInfo {
                 n: value.n as u8,
                 k: value.k as u8,
                 task_type: value.key_type,
+                sign_mode: SignMode::Recover,
+            },
+            payment: TaskPayment {
+                executor_fee: value.executor_fee,
+                initial_pinner_fee: value.initial_pinner_fee,
             },
         };
         validate_task_info(info)
@@ -34,6 +42,11 @@ impl From<crate::p2p_proto::SignCandidateRequest> for TaskInfo {
                 n: value.n as u8,
                 k: value.k as u8,
                 task_type: value.task_type,
+                sign_mode: SignMode::from_threshold_flag(value.threshold_sign),
+            },
+            payment: TaskPayment {
+                executor_fee: value.executor_fee,
+                initial_pinner_fee: 0,
             },
         }
     }
@@ -51,19 +64,34 @@ impl TryFrom<crate::actor_delegate_proto::KeyGenerationResponse> for TaskInfo {
                 n: value.data_adhoc.n as u8,
                 k: value.data_adhoc.k as u8,
                 task_type: value.data_adhoc.key_type,
+                sign_mode: SignMode::from_threshold_flag(value.data_adhoc.threshold_sign),
             },
+            payment: TaskPayment::from(&value.payment),
         };
         validate_task_info(info)
     }
 }
 
-fn validate_task_info(info: TaskInfo) -> anyhow::Result<TaskInfo> {
-    if info.exec_info.n == 0 {
-        return Err(anyhow::anyhow!("{}:{} invalid value n", line!(), file!()));
-    }
-    if info.exec_info.k == 0 || info.exec_info.k >= info.exec_info.n {
-        return Err(anyhow::anyhow!("{}:{} invalid value k", line!(), file!(),));
+impl TryFrom<crate::actor_delegate_proto::ReshareResponse> for TaskInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: crate::actor_delegate_proto::ReshareResponse) -> Result<Self, Self::Error> {
+        // key type is the same as the asset to reshare, it is filled after asset info queried
+        let info = TaskInfo {
+            task_id: base64::encode(&value.task_id),
+            exec_info: ExecutionInfo {
+                n: value.data_adhoc.n as u8,
+                k: value.data_adhoc.k as u8,
+                task_type: "".into(),
+                sign_mode: SignMode::Recover,
+            },
+            payment: TaskPayment::from(&value.payment),
+        };
+        validate_task_info(info)
     }
+}
 
+fn validate_task_info(info: TaskInfo) -> anyhow::Result<TaskInfo> {
+    validate_threshold(info.exec_info.n, info.exec_info.k)?;
     Ok(info)
 }
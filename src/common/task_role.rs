@@ -0,0 +1,187 @@
+use super::task_info::TaskInfo;
+use crate::BINDING_NAME;
+use tea_actor_utility::actor_kvp;
+use tea_actor_utility::actor_kvp::ShabbyLock;
+use tea_codec::error::TeaError;
+
+const PREFIX_TASK_ROLE_RECORD: &'static str = "task_role_record";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TaskRole {
+    Executor,
+    InitialPinner,
+}
+
+impl TaskRole {
+    /// Roles agreed to by answering a candidate request. Delegator may choose spare executor
+    /// candidates as initial pinners, so an executor candidate agrees to pin as well.
+    pub fn agreed_roles(apply_executor: bool) -> Vec<TaskRole> {
+        match apply_executor {
+            true => vec![TaskRole::Executor, TaskRole::InitialPinner],
+            false => vec![TaskRole::InitialPinner],
+        }
+    }
+
+    /// Prefix of the RSA private key sent in the key generation apply request of this role.
+    pub fn rsa_key_prefix(&self) -> &'static str {
+        match self {
+            TaskRole::Executor => "key_gen_rsa_key_executor",
+            TaskRole::InitialPinner => "key_gen_rsa_key_initial_pinner",
+        }
+    }
+}
+
+/// Roles a node agreed to for a key generation task, and the one it finally took.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRoleRecord {
+    pub task_info: TaskInfo,
+    /// Role the node applied as, its RSA key is shared by all agreed roles
+    pub applied: TaskRole,
+    pub agreed: Vec<TaskRole>,
+    pub taken: Option<TaskRole>,
+}
+
+impl TaskRoleRecord {
+    pub fn new(task_info: TaskInfo, apply_executor: bool) -> Self {
+        let agreed = TaskRole::agreed_roles(apply_executor);
+        TaskRoleRecord {
+            task_info,
+            applied: agreed[0],
+            agreed,
+            taken: None,
+        }
+    }
+
+    pub fn rsa_key_prefix(&self) -> &'static str {
+        self.applied.rsa_key_prefix()
+    }
+
+    pub fn has_agreed(&self, role: TaskRole) -> bool {
+        self.agreed.contains(&role)
+    }
+
+    /// A node takes at most one of the agreed roles of a task, taking the same role again is
+    /// allowed so that retried requests work.
+    pub fn take(&mut self, role: TaskRole) -> anyhow::Result<()> {
+        if !self.has_agreed(role) {
+            return Err(anyhow::anyhow!(
+                "{}:{} i have not agreed to be {:?} of task {}",
+                line!(),
+                file!(),
+                role,
+                &self.task_info.task_id
+            ));
+        }
+        match self.taken {
+            Some(taken) if taken != role => Err(anyhow::anyhow!(
+                "{}:{} i have been {:?} of task {} already",
+                line!(),
+                file!(),
+                taken,
+                &self.task_info.task_id
+            )),
+            _ => {
+                self.taken = Some(role);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn contains(task_id: &str) -> anyhow::Result<bool> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(
+            actor_kvp::get::<TaskRoleRecord>(BINDING_NAME, &get_task_role_record_key(task_id))?
+                .is_some(),
+        )
+    }
+
+    pub fn get(task_id: &str) -> anyhow::Result<Self> {
+        TaskRoleRecord::find(task_id)?
+            .ok_or(TeaError::CommonError(format!("can not find roles of task {}", task_id)).into())
+    }
+
+    /// Same as `get` except that a missing record is `None` rather than an error.
+    pub fn find(task_id: &str) -> anyhow::Result<Option<Self>> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(actor_kvp::get::<TaskRoleRecord>(
+            BINDING_NAME,
+            &get_task_role_record_key(task_id),
+        )?)
+    }
+
+    pub fn save(item: &TaskRoleRecord) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, &item.task_info.task_id);
+        actor_kvp::set_forever(
+            BINDING_NAME,
+            &get_task_role_record_key(&item.task_info.task_id),
+            item,
+        )?;
+        Ok(())
+    }
+
+    pub fn delete(task_id: &str) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        actor_kvp::del(BINDING_NAME, &get_task_role_record_key(task_id))?;
+        Ok(())
+    }
+
+    /// Loads the record and takes `role` in it, the record is saved only if the role is taken.
+    pub fn take_role(task_id: &str, role: TaskRole) -> anyhow::Result<Self> {
+        let mut record = TaskRoleRecord::get(task_id)?;
+        if record.taken != Some(role) {
+            record.take(role)?;
+            TaskRoleRecord::save(&record)?;
+        }
+        Ok(record)
+    }
+}
+
+fn get_task_role_record_key(task_id: &str) -> String {
+    format!("{}_{}", PREFIX_TASK_ROLE_RECORD, task_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{ExecutionInfo, TaskPayment};
+
+    fn new_record(apply_executor: bool) -> TaskRoleRecord {
+        TaskRoleRecord::new(
+            TaskInfo {
+                task_id: "task".into(),
+                exec_info: ExecutionInfo::default(),
+                payment: TaskPayment::default(),
+            },
+            apply_executor,
+        )
+    }
+
+    #[test]
+    fn executor_candidate_can_take_one_role() -> anyhow::Result<()> {
+        let mut record = new_record(true);
+        assert_eq!(TaskRole::Executor, record.applied);
+        record.take(TaskRole::InitialPinner)?;
+        record.take(TaskRole::InitialPinner)?;
+        assert!(record.take(TaskRole::Executor).is_err());
+        assert_eq!(Some(TaskRole::InitialPinner), record.taken);
+        Ok(())
+    }
+
+    #[test]
+    fn initial_pinner_candidate_can_not_execute() {
+        let mut record = new_record(false);
+        assert_eq!(TaskRole::InitialPinner, record.applied);
+        assert!(!record.has_agreed(TaskRole::Executor));
+        assert!(record.take(TaskRole::Executor).is_err());
+        assert_eq!(None, record.taken);
+    }
+
+    #[test]
+    fn rsa_key_belongs_to_applied_role() {
+        assert_ne!(
+            new_record(true).rsa_key_prefix(),
+            new_record(false).rsa_key_prefix()
+        );
+    }
+}
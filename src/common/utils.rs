@@ -1,7 +1,12 @@
 use crate::common::TaskInfo;
 use prost::Message;
 use std::collections::HashMap;
-use tea_actor_utility::{action, encode_protobuf, layer1::lookup_node_profile_by_tea_id};
+use tea_actor_utility::{
+    action,
+    actor_env::{get_my_peer_id, get_system_time},
+    encode_protobuf,
+    layer1::lookup_node_profile_by_tea_id,
+};
 use wascc_actor::prelude::codec::messaging::BrokerMessage;
 
 pub fn from_hash_map(
@@ -14,6 +19,15 @@ pub fn from_hash_map(
     properties
 }
 
+pub fn current_timestamp() -> anyhow::Result<u64> {
+    let now = get_system_time().map_err(|e| anyhow::anyhow!("{}", e))?;
+    Ok(now.duration_since(std::time::UNIX_EPOCH)?.as_secs())
+}
+
+pub fn my_peer_id() -> anyhow::Result<String> {
+    get_my_peer_id().map_err(|e| anyhow::anyhow!("{}", e))
+}
+
 pub fn send_ra_request(peer_id: String, properties: HashMap<String, String>) -> anyhow::Result<()> {
     action::call_async_intercom(
         crate::PINNER_ACTOR_NAME,
@@ -0,0 +1,137 @@
+use super::secret::{to_secret_buffers, SecretBuffer};
+use tea_actor_utility::actor_crypto;
+
+/// Feldman commitments of the polynomial used to split a private key, the first commitment
+/// commits to the constant term and so must equal the public key of the split private key.
+pub fn validate_commitments(
+    commitments: &[Vec<u8>],
+    public_key: &[u8],
+    k: u8,
+) -> anyhow::Result<()> {
+    if commitments.is_empty() || commitments.len() != k as usize {
+        return Err(anyhow::anyhow!(
+            "{}:{} expect {} share commitments, got {}",
+            line!(),
+            file!(),
+            k,
+            commitments.len()
+        ));
+    }
+    if commitments.iter().any(|c| c.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "{}:{} share commitment is empty",
+            line!(),
+            file!()
+        ));
+    }
+    if commitments[0].as_slice() != public_key {
+        return Err(anyhow::anyhow!(
+            "{}:{} share commitments do not match public key",
+            line!(),
+            file!()
+        ));
+    }
+    Ok(())
+}
+
+/// x-coordinate of the `position`-th (0-based) key slice returned by
+/// [`split_with_commitments`]. Share indexes carried with key slices are always x-coordinates,
+/// they start from 1 because the point at x = 0 is the private key itself.
+pub fn share_x_coordinate(position: usize) -> u32 {
+    position as u32 + 1
+}
+
+pub fn split_with_commitments(
+    key_type: &str,
+    n: u8,
+    k: u8,
+    private_key: &SecretBuffer,
+) -> anyhow::Result<(Vec<SecretBuffer>, Vec<Vec<u8>>)> {
+    let (key_slices, commitments) = actor_crypto::shamir_share_with_commitments(
+        key_type.to_string(),
+        n,
+        k,
+        private_key.expose(),
+    )?;
+    Ok((to_secret_buffers(key_slices), commitments))
+}
+
+/// Checks that `key_slice` at x-coordinate `index` lies on the committed polynomial whose
+/// constant term is the private key of `public_key`.
+pub fn verify_key_slice(
+    key_type: &str,
+    index: u32,
+    key_slice: &SecretBuffer,
+    commitments: &[Vec<u8>],
+    public_key: &[u8],
+    k: u8,
+) -> anyhow::Result<()> {
+    validate_commitments(commitments, public_key, k)?;
+    if index == 0 {
+        return Err(anyhow::anyhow!(
+            "{}:{} key slice index 0 is the private key",
+            line!(),
+            file!()
+        ));
+    }
+    if !actor_crypto::verify_shamir_share(
+        key_type.to_string(),
+        index,
+        key_slice.expose(),
+        commitments.to_vec(),
+    )? {
+        return Err(anyhow::anyhow!(
+            "{}:{} key slice {} is inconsistent with share commitments",
+            line!(),
+            file!(),
+            index
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commitments() -> Vec<Vec<u8>> {
+        vec![vec![1u8; 33], vec![2u8; 33], vec![3u8; 33]]
+    }
+
+    #[test]
+    fn valid_commitments_works() {
+        assert!(validate_commitments(&commitments(), &[1u8; 33], 3).is_ok());
+    }
+
+    #[test]
+    fn commitments_count_must_match_k() {
+        assert!(validate_commitments(&commitments(), &[1u8; 33], 2).is_err());
+        assert!(validate_commitments(&commitments(), &[1u8; 33], 4).is_err());
+        assert!(validate_commitments(&[], &[1u8; 33], 0).is_err());
+    }
+
+    #[test]
+    fn first_commitment_must_be_public_key() {
+        assert!(validate_commitments(&commitments(), &[2u8; 33], 3).is_err());
+        assert!(validate_commitments(&commitments(), &[1u8; 32], 3).is_err());
+    }
+
+    #[test]
+    fn share_x_coordinates_start_from_one() {
+        assert_eq!(1, share_x_coordinate(0));
+        assert_eq!(3, share_x_coordinate(2));
+    }
+
+    #[test]
+    fn key_slice_at_zero_is_rejected() {
+        let key_slice = SecretBuffer::from(vec![1u8; 32]);
+        assert!(verify_key_slice("", 0, &key_slice, &commitments(), &[1u8; 33], 3).is_err());
+    }
+
+    #[test]
+    fn empty_commitment_is_rejected() {
+        let mut commitments = commitments();
+        commitments[2] = vec![];
+        assert!(validate_commitments(&commitments, &[1u8; 33], 3).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+mod cancel;
 pub mod dump_methods;
 mod executor_info;
 mod handler;
@@ -5,15 +6,20 @@ mod key_gen;
 mod sign;
 mod verifier;
 
+pub use cancel::process_task_cancel_event;
 pub use handler::{
-    process_key_generation_event, process_sign_with_key_slices_event,
+    process_key_generation_event, process_reshare_event, process_sign_with_key_slices_event,
     task_commit_sign_result_request_handler, task_execution_response_handler,
     task_key_generation_apply_request_handler, task_pinner_key_slice_response_handler,
     task_sign_get_pinner_key_slice_response_handler, task_sign_with_key_slices_request_handler,
 };
 pub use key_gen::{
-    is_key_gen_tag, operation_after_verify_handler as key_gen_operation_after_verify_handler,
+    delete_store_item as delete_key_gen_store_item, is_key_gen_tag,
+    operation_after_verify_handler as key_gen_operation_after_verify_handler,
+    sweep_timeout_tasks as sweep_key_gen_timeout_tasks, task_state as key_gen_task_state,
 };
 pub use sign::{
-    is_sign_tag, operation_after_verify_handler as sign_operation_after_verify_handler,
+    delete_store_item as delete_sign_store_item, get_asset_info, get_sign_task, is_sign_tag,
+    operation_after_verify_handler as sign_operation_after_verify_handler,
+    task_state as sign_task_state,
 };
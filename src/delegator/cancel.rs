@@ -0,0 +1,42 @@
+use crate::common::send_task_cancel_request;
+use tea_actor_utility::ipfs_p2p::close_p2p;
+
+/// Layer1 asks to cancel a stale or disputed task, if i'm its delegator the task is marked as
+/// cancelled and all participants are told to tear it down.
+pub fn process_task_cancel_event(
+    res: crate::actor_delegate_proto::TaskCancelResponse,
+) -> anyhow::Result<()> {
+    let task_id = base64::encode(&res.task_id);
+    let peer_ids = match super::key_gen::cancel_task(&task_id)? {
+        Some(peer_ids) => peer_ids,
+        None => match super::sign::cancel_task(&task_id)? {
+            Some(peer_ids) => peer_ids,
+            None => {
+                trace!("i'm not delegator of task {}, just ignore", &task_id);
+                return Ok(());
+            }
+        },
+    };
+
+    info!(
+        "cancelled task {}, reason: {}, notifying {} participants",
+        &task_id,
+        &res.reason,
+        peer_ids.len()
+    );
+    for peer_id in peer_ids.iter() {
+        if let Err(e) = send_task_cancel_request(peer_id, &task_id, &res.reason) {
+            warn!(
+                "failed to send cancel request of task {} to {}, details: {}",
+                &task_id, peer_id, e
+            );
+        }
+        if let Err(e) = close_p2p(peer_id) {
+            warn!(
+                "failed to close p2p connection of task {} to {}, details: {}",
+                &task_id, peer_id, e
+            );
+        }
+    }
+    Ok(())
+}
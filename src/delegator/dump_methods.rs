@@ -14,9 +14,26 @@ use wascc_actor::prelude::codec::messaging::BrokerMessage;
 use wascc_actor::HandlerResult;
 
 const DEPLOYMENT_IDS_KEY: &str = "predefined deployments ids";
+const KEY_TYPE_KEY: &str = "predefined key type";
+const P1_PUBLIC_KEY_KEY: &str = "predefined p1 public key";
+/// Fixed block hash of mocked key generation tasks, so that dev elections are reproducible
+const DEV_BLOCK_HASH: [u8; 32] = [0x5a; 32];
 
-pub fn get_deployment_ids() -> anyhow::Result<Option<Vec<String>>> {
-    actor_kvp::get::<Vec<String>>(BINDING_NAME, DEPLOYMENT_IDS_KEY)
+pub fn get_asset_info() -> anyhow::Result<Option<crate::actor_delegate_proto::AssetInfo>> {
+    let deployment_ids = match actor_kvp::get::<Vec<String>>(BINDING_NAME, DEPLOYMENT_IDS_KEY)? {
+        Some(ids) if !ids.is_empty() => ids,
+        _ => return Ok(None),
+    };
+    let key_type = actor_kvp::get::<String>(BINDING_NAME, KEY_TYPE_KEY)?.unwrap_or_default();
+    let p1 = actor_kvp::get::<Vec<u8>>(BINDING_NAME, P1_PUBLIC_KEY_KEY)?.unwrap_or_default();
+    Ok(Some(crate::actor_delegate_proto::AssetInfo {
+        n: 2,
+        k: 1,
+        key_type,
+        p1,
+        p2_deployment_ids: deployment_ids,
+        ..Default::default()
+    }))
 }
 
 pub fn generate_sign_response_message(msg: &BrokerMessage) -> HandlerResult<()> {
@@ -35,6 +52,7 @@ pub fn generate_sign_response_message(msg: &BrokerMessage) -> HandlerResult<()>
         deployment_ids.push(params[i].to_string());
     }
     actor_kvp::set(BINDING_NAME, DEPLOYMENT_IDS_KEY, &deployment_ids, 6000)?;
+    actor_kvp::set(BINDING_NAME, KEY_TYPE_KEY, &key_type, 6000)?;
     debug!(
         "generate_key_gen_response_message with key type: {}, multi_sig_account: {}, deployment_ids: {:?}",
         &params[0], &params[1], &deployment_ids
@@ -44,8 +62,9 @@ pub fn generate_sign_response_message(msg: &BrokerMessage) -> HandlerResult<()>
         reply_to,
         move |delegator_tea_nonce_hash, delegator_tea_nonce_rsa_encryption| {
             let transaction_data = "hello world!".as_bytes().to_vec();
-            let (_, p1_priv_key) = generate(key_type.clone())?;
-            let p1_signature = sign(key_type.clone(), p1_priv_key, transaction_data.clone())?;
+            let (p1_pub_key, p1_priv_key) = generate(key_type.clone())?;
+            actor_kvp::set(BINDING_NAME, P1_PUBLIC_KEY_KEY, &p1_pub_key, 6000)?;
+            let p1_signature = sign(key_type.clone(), &p1_priv_key, transaction_data.clone())?;
             let res = crate::actor_delegate_proto::SignTransactionResponse {
                 task_id: get_uuid().as_bytes().to_vec(),
                 data_adhoc: crate::actor_delegate_proto::SignTransactionData {
@@ -53,7 +72,10 @@ pub fn generate_sign_response_message(msg: &BrokerMessage) -> HandlerResult<()>
                     delegator_tea_nonce_hash,
                     delegator_tea_nonce_rsa_encryption,
                 },
-                payment: crate::actor_delegate_proto::TaskPaymentDescription {},
+                payment: crate::actor_delegate_proto::TaskPaymentDescription {
+                    executor_fee: 0,
+                    initial_pinner_fee: 0,
+                },
                 p1_signature,
                 multi_sig_account: multi_sig_account.clone(),
             };
@@ -83,9 +105,14 @@ pub fn generate_key_gen_response_message(msg: &BrokerMessage) -> HandlerResult<(
                     key_type: key_type.clone(),
                     delegator_tea_nonce_hash,
                     delegator_tea_nonce_rsa_encryption,
+                    block_hash: DEV_BLOCK_HASH.to_vec(),
+                },
+                payment: crate::actor_delegate_proto::TaskPaymentDescription {
+                    executor_fee: 0,
+                    initial_pinner_fee: 0,
                 },
-                payment: crate::actor_delegate_proto::TaskPaymentDescription {},
                 p1_public_key,
+                p3_public_key: Vec::new(),
             };
             Ok(base64::encode(encode_protobuf(res)?))
         },
@@ -3,4 +3,6 @@
 pub struct ExecutorInfo {
     pub peer_id: String,
     pub rsa_pub_key: Vec<u8>,
+    #[serde(default)]
+    pub ephemeral_id: Vec<u8>,
 }
@@ -19,6 +19,9 @@ pub fn task_sign_get_pinner_key_slice_response_handler(
     peer_id: &str,
     reply_to: &str,
 ) -> anyhow::Result<()> {
+    if super::key_gen::is_reshare_task(&res.task_id) {
+        return super::key_gen::process_reshare_key_slice_response(res, peer_id, reply_to);
+    }
     super::sign::process_pinner_key_slice_response(res, peer_id, reply_to)
 }
 
@@ -70,3 +73,9 @@ pub fn process_sign_with_key_slices_event(
 ) -> anyhow::Result<()> {
     super::sign::process_sign_with_key_slices_event(req)
 }
+
+pub fn process_reshare_event(
+    req: crate::actor_delegate_proto::ReshareResponse,
+) -> anyhow::Result<()> {
+    super::key_gen::process_reshare_event(req)
+}
@@ -1,28 +1,52 @@
-use crate::common::{send_key_candidate_request, utils::invite_candidate_executors};
+use crate::common::{
+    idempotency,
+    retention::TaskKind,
+    send_key_candidate_request, send_signed_message, send_task_cancel_request,
+    utils::{current_timestamp, invite_candidate_executors},
+    TaskInfo,
+};
 use crate::delegator::executor_info::ExecutorInfo;
 use crate::delegator::key_gen::initial_pinner_info::InitialPinnerInfo;
+use crate::BINDING_NAME;
 use std::convert::{TryFrom, TryInto};
-use store_item::{DelegatorKeyGenStoreItem, StoreItemState};
+use store_item::{
+    add_pending_task, pending_task_ids, remove_pending_task, DelegatorKeyGenStoreItem,
+    StoreItemEvent, StoreItemState,
+};
 use tea_actor_utility::{
-    action,
+    action, actor_kvp,
     actor_nats::response_reply_with_subject,
     encode_protobuf,
-    ipfs_p2p::{log_and_response, log_and_response_with_error, send_message, P2pReplyType},
+    ipfs_p2p::{log_and_response, log_and_response_with_error, P2pReplyType},
 };
 
 mod candidates;
 mod initial_pinner_info;
 mod observers;
 mod ra;
+mod reshare;
 mod store_item;
 
 pub use observers::{is_key_gen_tag, operation_after_verify_handler};
 pub use ra::{remote_attestation_executor, remote_attestation_initial_pinner};
+pub use reshare::{
+    is_reshare_task, process_pinner_key_slice_response as process_reshare_key_slice_response,
+    process_reshare_event,
+};
 use tea_actor_utility::ipfs_p2p::close_p2p;
 use wascc_actor::HandlerResult;
 
+pub const CANDIDATES_ELECTION_TIMEOUT_SECONDS_KEY: &'static str =
+    "gluon_candidates_election_timeout_seconds";
+pub const KEY_SLICES_COLLECTION_TIMEOUT_SECONDS_KEY: &'static str =
+    "gluon_key_slices_collection_timeout_seconds";
+const DEFAULT_CANDIDATES_ELECTION_TIMEOUT_SECONDS: u64 = 120;
+const DEFAULT_KEY_SLICES_COLLECTION_TIMEOUT_SECONDS: u64 = 120;
+
 pub trait TaskCandidates {
     fn ready(&self) -> bool;
+    fn is_timeout(&self, now: u64) -> bool;
+    fn ready_on_timeout(&self) -> bool;
     fn insert_executor(&mut self, executor: ExecutorInfo);
     fn insert_initial_pinner(&mut self, pinner: InitialPinnerInfo);
     fn elect(&mut self) -> anyhow::Result<()>;
@@ -45,33 +69,107 @@ pub fn process_key_generation_event(
             store_item.nonce = nonce;
             DelegatorKeyGenStoreItem::save(&store_item)?;
 
-            invite_candidate_executors(
-                store_item.task_info.clone(),
-                |task_info, peer_id| {
-                    debug!("begin to invite executor delegate {}", &peer_id);
-                    send_key_candidate_request(&peer_id, task_info, true)?;
-                    Ok(())
-                },
-                |task_info, peer_ids| {
-                    Ok(candidates::invite_candidate_initial_pinners(
-                        task_info, peer_ids,
-                    )?)
-                },
-            )?;
-            store_item.state = StoreItemState::InvitedCandidates;
-            DelegatorKeyGenStoreItem::save(&store_item)?;
+            invite_candidates(&mut store_item)?;
             Ok(())
         },
     )
 }
 
+fn invite_candidates(store_item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
+    invite_candidate_executors(
+        store_item.task_info.clone(),
+        |task_info, peer_id| {
+            debug!("begin to invite executor delegate {}", &peer_id);
+            send_key_candidate_request(&peer_id, task_info, true)?;
+            Ok(())
+        },
+        |task_info, peer_ids| {
+            Ok(candidates::invite_candidate_initial_pinners(
+                task_info, peer_ids,
+            )?)
+        },
+    )?;
+    store_item.transition(StoreItemEvent::CandidatesInvited, None)?;
+    store_item.deadline = Some(
+        current_timestamp()?
+            + timeout_seconds(
+                CANDIDATES_ELECTION_TIMEOUT_SECONDS_KEY,
+                DEFAULT_CANDIDATES_ELECTION_TIMEOUT_SECONDS,
+            )?,
+    );
+    DelegatorKeyGenStoreItem::save(store_item)?;
+    add_pending_task(&store_item.task_info.task_id)
+}
+
+/// Timeout configured under `key`, `default` if nothing is configured.
+fn timeout_seconds(key: &str, default: u64) -> anyhow::Result<u64> {
+    Ok(actor_kvp::get::<u64>(BINDING_NAME, key)?.unwrap_or(default))
+}
+
+pub fn sweep_timeout_tasks(now: u64) -> anyhow::Result<()> {
+    for task_id in pending_task_ids()? {
+        match DelegatorKeyGenStoreItem::get(&task_id) {
+            Ok(mut item) => {
+                if let Err(e) = try_elect_on_timeout(&mut item, now) {
+                    error!("failed to elect timeout task {}, details: {}", &task_id, e);
+                }
+            }
+            Err(e) => {
+                warn!("pending task {} is missing, details: {}", &task_id, e);
+                remove_pending_task(&task_id)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn try_elect_on_timeout(item: &mut DelegatorKeyGenStoreItem, now: u64) -> anyhow::Result<()> {
+    match item.state {
+        StoreItemState::InvitedCandidates => {}
+        // executor of the reshare task is elected, key slices are being collected from pinners
+        StoreItemState::CollectingKeySlices => {
+            if !item.is_timeout(now) {
+                return Ok(());
+            }
+            warn!(
+                "reshare task {} timeout without enough key slices, k is {}",
+                &item.task_info.task_id,
+                item.reshare.as_ref().map(|v| v.k).unwrap_or_default()
+            );
+            return fail_task(item, "not enough key slices before collection timeout");
+        }
+        _ => return remove_pending_task(&item.task_info.task_id),
+    }
+    if !item.is_timeout(now) {
+        return Ok(());
+    }
+
+    if !item.ready_on_timeout() {
+        warn!(
+            "task {} timeout without enough candidates, k is {}",
+            &item.task_info.task_id, item.task_info.exec_info.k
+        );
+        return fail_task(item, "not enough candidates before election timeout");
+    }
+
+    info!(
+        "task {} timeout, force to elect with available candidates",
+        &item.task_info.task_id
+    );
+    item.shrink_to_available_candidates();
+    send_to_executor(item)
+}
+
 fn try_send_to_executor(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
-    if (item as &mut dyn TaskCandidates).ready() {
-        item.elect()?;
-    } else {
+    if !(item as &mut dyn TaskCandidates).ready() {
         debug!("continue to wait more candidates...");
         return Ok(());
     }
+    send_to_executor(item)
+}
+
+fn send_to_executor(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
+    item.elect()?;
 
     if !(item as &mut dyn ExecutorRequestConstructor).ready() {
         return Err(anyhow::anyhow!(
@@ -80,12 +178,34 @@ fn try_send_to_executor(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<(
         ));
     }
 
+    if let Some(reshare) = item.reshare.as_ref() {
+        if !reshare.ready() {
+            info!(
+                "executor of reshare task {} elected, begin to collect key slices",
+                &item.task_info.task_id
+            );
+            // the task stays pending so that it fails if key slices are not collected in time
+            item.deadline = Some(
+                current_timestamp()?
+                    + timeout_seconds(
+                        KEY_SLICES_COLLECTION_TIMEOUT_SECONDS_KEY,
+                        DEFAULT_KEY_SLICES_COLLECTION_TIMEOUT_SECONDS,
+                    )?,
+            );
+            return reshare::request_key_slices(item);
+        }
+    }
+    send_execution_request(item)
+}
+
+fn send_execution_request(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
     info!(
         "collect enough candidates, begin to send request to executor: {}",
         &item.executor.as_ref().unwrap().peer_id
     );
     let req = item.generate()?;
-    send_message(
+    item.transition(StoreItemEvent::ExecutionRequested, None)?;
+    send_signed_message(
         &item.executor.as_ref().unwrap().peer_id,
         &item.task_info.task_id,
         crate::p2p_proto::GeneralMsg {
@@ -94,9 +214,8 @@ fn try_send_to_executor(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<(
             )),
         },
     )?;
-
-    item.state = StoreItemState::SentToExecutor;
-    DelegatorKeyGenStoreItem::save(item)
+    DelegatorKeyGenStoreItem::save(item)?;
+    remove_pending_task(&item.task_info.task_id)
 }
 
 pub fn process_task_execution_response(
@@ -108,28 +227,69 @@ pub fn process_task_execution_response(
         "process_task_execution_response from {} with response {:?}",
         peer_id, &res
     );
-    delegator_store_item_handler(&res.task_id, peer_id, reply_to, |item| {
+    let key = idempotency::message_key(
+        "TaskExecutionResponse",
+        peer_id,
+        &encode_protobuf(res.clone())?,
+    );
+    if !idempotency::claim(TaskKind::DelegatorKeyGen, &res.task_id, &key)? {
+        return idempotency::acknowledge_duplicate(reply_to, &res.task_id);
+    }
+    let result = delegator_store_item_handler(&res.task_id, peer_id, reply_to, |item| {
         let mut item = item;
+        if !item.is_executor(peer_id) {
+            return log_and_response(
+                reply_to,
+                peer_id,
+                &res.task_id,
+                &format!("only elected executor of task {} can respond", &res.task_id),
+                P2pReplyType::Rejected,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e));
+        }
+        if let Some(reshare) = item.reshare.as_ref() {
+            if !reshare.is_same_account(&res.p2_public_key, &res.multi_sig_account) {
+                warn!(
+                    "reshare task {} changed multi-sig account, reject it",
+                    &res.task_id
+                );
+                item.transition(StoreItemEvent::Failed, Some(peer_id))?;
+                DelegatorKeyGenStoreItem::save(&item)?;
+                idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, &key)?;
+                return log_and_response(
+                    reply_to,
+                    peer_id,
+                    &res.task_id,
+                    "resharing should not change multi-sig account",
+                    P2pReplyType::Rejected,
+                )
+                .map_err(|e| anyhow::anyhow!("{}", e));
+            }
+        }
+        // the item is saved only after all key slices are shared, so that a resent response
+        // starts over from the same state if sharing fails halfway
         item.p2_public_key = Some(res.p2_public_key.clone());
         item.multi_sig_account = Some(res.multi_sig_account.clone());
-        item.state = StoreItemState::ReceivedExecutionResult;
-        DelegatorKeyGenStoreItem::save(&item)?;
+        item.transition(StoreItemEvent::ExecutionResultReceived, Some(peer_id))?;
 
         for pinner_data in res.initial_pinners.iter() {
             share_slices_to_initial_pinner(
-                &item.task_info.task_id,
+                &item.task_info,
                 pinner_data,
                 &res.p2_public_key,
                 &res.multi_sig_account,
+                &res.share_commitments,
             )?;
             item.initial_pinner_responses
                 .insert(pinner_data.peer_id.clone(), None);
         }
-        item.state = StoreItemState::SentToInitialPinner;
+        item.transition(StoreItemEvent::KeySlicesShared, None)?;
         DelegatorKeyGenStoreItem::save(&item)?;
+        idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, &key)?;
 
         response_reply_with_subject("", reply_to, "received task response".as_bytes().to_vec())
-    })
+    });
+    idempotency::release_on_error(TaskKind::DelegatorKeyGen, &res.task_id, &key, result)
 }
 
 pub fn process_task_pinner_key_slice_response(
@@ -141,9 +301,43 @@ pub fn process_task_pinner_key_slice_response(
         "process_task_pinner_key_slice_response from {} with response: {:?}",
         peer_id, &res
     );
-    delegator_store_item_handler(&res.task_id, peer_id, reply_to, |item| {
+    let key = idempotency::message_key(
+        "TaskPinnerKeySliceResponse",
+        peer_id,
+        &encode_protobuf(res.clone())?,
+    );
+    if !idempotency::claim(TaskKind::DelegatorKeyGen, &res.task_id, &key)? {
+        return idempotency::acknowledge_duplicate(reply_to, &res.task_id);
+    }
+    let result = delegator_store_item_handler(&res.task_id, peer_id, reply_to, |item| {
         let mut item = item;
+        if !item.is_initial_pinner(peer_id) {
+            return log_and_response(
+                reply_to,
+                peer_id,
+                &res.task_id,
+                &format!(
+                    "only initial pinners of task {} can respond key slice",
+                    &res.task_id
+                ),
+                P2pReplyType::Rejected,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e));
+        }
         match item.initial_pinner_responses.get_mut(peer_id) {
+            Some(Some(_)) => {
+                return log_and_response(
+                    reply_to,
+                    peer_id,
+                    &res.task_id,
+                    &format!(
+                        "initial pinner {} has responded task {} already",
+                        peer_id, &res.task_id
+                    ),
+                    P2pReplyType::Rejected,
+                )
+                .map_err(|e| anyhow::anyhow!("{}", e))
+            }
             Some(response_value) => {
                 *response_value = Some(res.deployment_id.clone());
             }
@@ -162,10 +356,30 @@ pub fn process_task_pinner_key_slice_response(
             }
         }
 
-        if item.is_all_initial_pinners_ready() {
+        if item.is_all_initial_pinners_ready() && item.reshare.is_some() {
+            debug!("all initial pinners ready, begin to update reshare result");
+            item.transition(StoreItemEvent::AllPinnersResponded, Some(peer_id))?;
+            DelegatorKeyGenStoreItem::save(&item)?;
+            idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, &key)?;
+
+            let result: crate::actor_delegate_proto::UpdateReshareResult =
+                item.clone().try_into()?;
+            let task_id = item.task_info.task_id.clone();
+            action::call(
+                "layer1.async.reply.update_reshare_result",
+                "actor.gluon.inbox",
+                base64::encode(&encode_protobuf(result)?).into(),
+                move |msg| {
+                    debug!("update_reshare_result got response: {:?}", msg);
+                    close_p2p_connections(&task_id)
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e))
+        } else if item.is_all_initial_pinners_ready() {
             debug!("all initial pinners ready, begin to update key generation result");
-            item.state = StoreItemState::ReceivedAllPinnerResponse;
+            item.transition(StoreItemEvent::AllPinnersResponded, Some(peer_id))?;
             DelegatorKeyGenStoreItem::save(&item)?;
+            idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, &key)?;
 
             let result: crate::actor_delegate_proto::UpdateKeyGenerationResult =
                 item.clone().try_into()?;
@@ -182,6 +396,7 @@ pub fn process_task_pinner_key_slice_response(
             .map_err(|e| anyhow::anyhow!("{}", e))
         } else {
             DelegatorKeyGenStoreItem::save(&item)?;
+            idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, &key)?;
             response_reply_with_subject(
                 "",
                 reply_to,
@@ -190,7 +405,75 @@ pub fn process_task_pinner_key_slice_response(
                     .to_vec(),
             )
         }
-    })
+    });
+    idempotency::release_on_error(TaskKind::DelegatorKeyGen, &res.task_id, &key, result)
+}
+
+pub fn delete_store_item(task_id: &str) -> anyhow::Result<()> {
+    DelegatorKeyGenStoreItem::delete(task_id)
+}
+
+pub fn task_state(task_id: &str) -> anyhow::Result<Option<String>> {
+    Ok(DelegatorKeyGenStoreItem::find(task_id)?.map(|item| format!("{:?}", item.state)))
+}
+
+/// Returns peers to be told to tear down the task, finished tasks are left untouched. `None` if
+/// i have no such task as delegator.
+pub fn cancel_task(task_id: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let mut item = match DelegatorKeyGenStoreItem::find(task_id)? {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    if item.is_terminal() {
+        info!(
+            "key generation task {} has finished with state {:?}, just ignore",
+            task_id, &item.state
+        );
+        return Ok(Some(Vec::new()));
+    }
+    item.transition(StoreItemEvent::Cancelled, None)?;
+    DelegatorKeyGenStoreItem::save(&item)?;
+    remove_pending_task(task_id)?;
+    Ok(Some(item.participant_peer_ids()))
+}
+
+/// Fails the task, tells all participants to tear it down and reports the failure to layer1.
+fn fail_task(item: &mut DelegatorKeyGenStoreItem, reason: &str) -> anyhow::Result<()> {
+    item.transition(StoreItemEvent::Failed, None)?;
+    DelegatorKeyGenStoreItem::save(item)?;
+    remove_pending_task(&item.task_info.task_id)?;
+
+    let task_id = item.task_info.task_id.clone();
+    for peer_id in item.participant_peer_ids() {
+        if let Err(e) = send_task_cancel_request(&peer_id, &task_id, reason) {
+            warn!(
+                "failed to send cancel request of task {} to {}, details: {}",
+                &task_id, &peer_id, e
+            );
+        }
+        if let Err(e) = close_p2p(&peer_id) {
+            warn!(
+                "failed to close p2p connection of task {} to {}, details: {}",
+                &task_id, &peer_id, e
+            );
+        }
+    }
+
+    let failure = crate::actor_delegate_proto::UpdateTaskFailed {
+        task_id: base64::decode(&task_id)?,
+        delegator_nonce: item.nonce.clone(),
+        reason: reason.to_string(),
+    };
+    action::call(
+        "layer1.async.reply.update_task_failed",
+        "actor.gluon.inbox",
+        base64::encode(&encode_protobuf(failure)?).into(),
+        move |msg| {
+            debug!("update_task_failed of {} got response: {:?}", &task_id, msg);
+            Ok(())
+        },
+    )
+    .map_err(|e| anyhow::anyhow!("{}", e))
 }
 
 fn close_p2p_connections(task_id: &str) -> HandlerResult<()> {
@@ -214,6 +497,14 @@ where
     T: Fn(&mut DelegatorKeyGenStoreItem) -> anyhow::Result<()>,
 {
     match DelegatorKeyGenStoreItem::get(task_id) {
+        Ok(ref item) if item.state == StoreItemState::Cancelled => log_and_response(
+            reply_to,
+            peer_id,
+            task_id,
+            &format!("task {} has been cancelled", task_id),
+            P2pReplyType::Rejected,
+        )
+        .map_err(|e| anyhow::anyhow!("{}", e)),
         Ok(ref mut item) => action(item),
         Err(e) => log_and_response_with_error(
             reply_to,
@@ -228,22 +519,25 @@ where
 }
 
 fn share_slices_to_initial_pinner(
-    task_id: &str,
+    task_info: &TaskInfo,
     data: &crate::p2p_proto::TaskResultInitialPinnerData,
     pub_key: &[u8],
     multi_sig_account: &[u8],
+    share_commitments: &[Vec<u8>],
 ) -> anyhow::Result<()> {
-    send_message(
+    send_signed_message(
         &data.peer_id,
-        task_id,
+        &task_info.task_id,
         crate::p2p_proto::GeneralMsg {
             msg: Some(
                 crate::p2p_proto::general_msg::Msg::TaskPinnerKeySliceRequest(
                     crate::p2p_proto::TaskPinnerKeySliceRequest {
-                        task_id: task_id.to_string(),
+                        task_id: task_info.task_id.clone(),
                         public_key: pub_key.to_vec(),
                         encrypted_key_slice: data.encrypted_key_slice.clone(),
                         multi_sig_account: multi_sig_account.to_vec(),
+                        share_commitments: share_commitments.to_vec(),
+                        threshold_sign: task_info.exec_info.sign_mode.is_threshold(),
                     },
                 ),
             ),
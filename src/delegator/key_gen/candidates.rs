@@ -1,4 +1,5 @@
 use crate::common::{send_key_candidate_request, TaskInfo};
+use sha2::{Digest, Sha256};
 use tea_actor_utility::actor_ipfs::ipfs_swarm_peers;
 
 pub fn invite_candidate_initial_pinners(
@@ -11,52 +12,40 @@ pub fn invite_candidate_initial_pinners(
         .filter(move |v| !filter_ids.contains(v))
         .collect();
 
-    let candidates: Vec<String> =
-        random_select_peers(peers_ids, task_info.exec_info.n, &task_info.task_id)
-            .into_iter()
-            .take(task_info.exec_info.n as usize * 2)
-            .collect();
+    let candidates = random_select_peers(
+        peers_ids,
+        task_info.exec_info.n as usize * 2,
+        &task_info.task_id,
+    );
     for peer_id in candidates {
         send_key_candidate_request(&peer_id, task_info.clone(), false)?;
     }
     Ok(())
 }
 
-fn random_select_peers(ids: Vec<String>, n: u8, task_id: &str) -> Vec<String> {
-    let candidates_count = n as u32 * 2;
-    if ids.len() < candidates_count as usize {
-        return ids;
-    }
-    let lucky_number = calculate_lucky_number(n, &task_id);
-
+/// Rendezvous hashing: peers are ordered by `sha256(task_id || peer_id)`, so each task gets a
+/// stable, uniformly distributed selection that can not be targeted by grinding peer ids.
+fn random_select_peers(ids: Vec<String>, count: usize, task_id: &str) -> Vec<String> {
     let mut ids = ids;
-    let mut distance = 0u8;
-    let mut candidates_peers = Vec::<String>::new();
-    while candidates_peers.len() < candidates_count as usize && !ids.is_empty() {
-        ids = ids
-            .into_iter()
-            .filter(|item| {
-                let lucky = calculate_lucky_number(n, item);
-                if (lucky as i16 - lucky_number as i16).abs() as u8 <= distance {
-                    candidates_peers.push(item.clone());
-                    false
-                } else {
-                    true
-                }
-            })
-            .collect();
-        distance += 1;
-    }
-    candidates_peers
+    ids.sort();
+    ids.dedup();
+    ids.sort_by_cached_key(|id| peer_score(task_id, id));
+    ids.truncate(count);
+    ids
 }
 
-fn calculate_lucky_number(n: u8, id: &str) -> u8 {
-    id.as_bytes()[id.as_bytes().len() - 1] % n
+fn peer_score(task_id: &str, peer_id: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update((task_id.len() as u32).to_le_bytes());
+    hasher.update(task_id.as_bytes());
+    hasher.update(peer_id.as_bytes());
+    hasher.finalize().to_vec()
 }
 
 #[cfg(test)]
 mod tests {
     use super::random_select_peers;
+    use std::collections::HashMap;
 
     #[test]
     fn random_select_peers_works() -> anyhow::Result<()> {
@@ -67,22 +56,77 @@ mod tests {
         let task_id = String::from(0u8 as char);
 
         // boundary test
-        assert_eq!(256, random_select_peers(peers.clone(), 255, &task_id).len());
-        assert_eq!(256, random_select_peers(peers.clone(), 1, &task_id).len());
+        assert_eq!(256, random_select_peers(peers.clone(), 510, &task_id).len());
+        assert_eq!(2, random_select_peers(peers.clone(), 2, &task_id).len());
+        assert!(random_select_peers(peers.clone(), 0, &task_id).is_empty());
+        assert!(random_select_peers(Vec::new(), 2, &task_id).is_empty());
 
         // normal test
-        assert_eq!(128, random_select_peers(peers.clone(), 2, &task_id).len());
-        assert_eq!(85, random_select_peers(peers.clone(), 3, &task_id).len());
+        assert_eq!(4, random_select_peers(peers.clone(), 4, &task_id).len());
+        assert_eq!(6, random_select_peers(peers.clone(), 6, &task_id).len());
 
         // double peers
         for i in 0..=255u8 {
             peers.push(String::from(i as char));
         }
-        assert_eq!(512, random_select_peers(peers.clone(), 255, &task_id).len());
-        assert_eq!(512, random_select_peers(peers.clone(), 1, &task_id).len());
-        assert_eq!(256, random_select_peers(peers.clone(), 2, &task_id).len());
-        assert_eq!(170, random_select_peers(peers.clone(), 3, &task_id).len());
+        assert_eq!(256, random_select_peers(peers.clone(), 510, &task_id).len());
+        assert_eq!(4, random_select_peers(peers.clone(), 4, &task_id).len());
 
         Ok(())
     }
+
+    #[test]
+    fn random_select_peers_is_stable() {
+        let peers: Vec<String> = (0..64).map(|i| format!("QmPeer{}", i)).collect();
+        let selected = random_select_peers(peers.clone(), 8, "task");
+
+        let mut reversed = peers.clone();
+        reversed.reverse();
+        assert_eq!(selected, random_select_peers(reversed, 8, "task"));
+
+        // a prefix of a longer selection is the shorter selection
+        assert_eq!(
+            selected[..4],
+            random_select_peers(peers.clone(), 4, "task")[..]
+        );
+        assert_ne!(selected, random_select_peers(peers, 8, "another task"));
+    }
+
+    #[test]
+    fn random_select_peers_distribution() {
+        let peers_count = 64;
+        let select_count = 8;
+        let tasks_count = 2000;
+        // peer ids share the same last byte, which skewed the former lucky number
+        let peers: Vec<String> = (0..peers_count).map(|i| format!("QmPeer{}x", i)).collect();
+
+        let mut hits: HashMap<String, u32> = HashMap::new();
+        for task in 0..tasks_count {
+            for peer in random_select_peers(peers.clone(), select_count, &format!("task{}", task)) {
+                *hits.entry(peer).or_default() += 1;
+            }
+        }
+
+        // every peer is expected to be selected 250 times, the standard deviation is about 15
+        let expected = tasks_count * select_count as u32 / peers_count;
+        assert_eq!(peers_count as usize, hits.len());
+        for (peer, count) in hits.iter() {
+            assert!(
+                *count > expected - 100 && *count < expected + 100,
+                "peer {} selected {} times, expected about {}",
+                peer,
+                count,
+                expected
+            );
+        }
+
+        // the first position is also evenly distributed
+        let mut firsts: HashMap<String, u32> = HashMap::new();
+        for task in 0..tasks_count {
+            let first = random_select_peers(peers.clone(), 1, &format!("task{}", task));
+            *firsts.entry(first[0].clone()).or_default() += 1;
+        }
+        let max = firsts.values().max().cloned().unwrap_or_default();
+        assert!(max < tasks_count / peers_count * 3);
+    }
 }
@@ -3,17 +3,21 @@ use crate::delegator::{
     key_gen::{
         initial_pinner_info::InitialPinnerInfo,
         ra::{
-            is_executor_ra_response, is_initial_pinner_ra_response, PROPERTY_RSA_PUB_KEY,
-            PROPERTY_TASK_ID,
+            is_executor_ra_response, is_initial_pinner_ra_response, is_pinner_ra_response,
+            PROPERTY_RSA_PUB_KEY, PROPERTY_TASK_ID,
         },
+        reshare,
         store_item::{DelegatorKeyGenStoreItem, StoreItemState},
         try_send_to_executor, TaskCandidates,
     },
 };
 
+// this property value set in pinner actor in response_peer_approve_pinner_handler method
+const PROPERTY_KEY_DEPLOYMENT_ID: &'static str = "deployment_id";
+
 pub fn operation_after_verify_handler(
     peer_id: String,
-    _ephemeral_id: Vec<u8>,
+    ephemeral_id: Vec<u8>,
     item: &crate::actor_pinner_proto::ChallangeStoreItem,
 ) -> anyhow::Result<()> {
     debug!("operation_after_verify_handler item: {:?}", item);
@@ -28,6 +32,21 @@ pub fn operation_after_verify_handler(
             &item.uuid
         ))?
         .value;
+    if is_pinner_ra_response(item) {
+        let deployment_id = &item
+            .properties
+            .iter()
+            .find(|v| PROPERTY_KEY_DEPLOYMENT_ID.eq(&v.key))
+            .ok_or(anyhow::anyhow!(
+                "{}:{} failed to get deployment_id id from ChallengeStoreItem {}",
+                line!(),
+                file!(),
+                &item.uuid
+            ))?
+            .value;
+        return reshare::on_pinner_ra_success(task_id, &peer_id, deployment_id);
+    }
+
     let rsa_pub_key = base64::decode(
         item.properties
             .iter()
@@ -43,7 +62,7 @@ pub fn operation_after_verify_handler(
     )?;
 
     if is_executor_ra_response(item) {
-        on_executor_ra_success(&task_id, &peer_id, rsa_pub_key)?;
+        on_executor_ra_success(&task_id, &peer_id, ephemeral_id, rsa_pub_key)?;
     } else if is_initial_pinner_ra_response(item) {
         on_initial_pinner_ra_success(&task_id, &peer_id, rsa_pub_key)?;
     }
@@ -53,6 +72,7 @@ pub fn operation_after_verify_handler(
 pub fn on_executor_ra_success(
     task_id: &str,
     peer_id: &str,
+    ephemeral_id: Vec<u8>,
     rsa_pub_key: Vec<u8>,
 ) -> anyhow::Result<()> {
     let mut store_item = DelegatorKeyGenStoreItem::get(task_id)?;
@@ -65,6 +85,7 @@ pub fn on_executor_ra_success(
     store_item.insert_executor(ExecutorInfo {
         peer_id: peer_id.to_string(),
         rsa_pub_key,
+        ephemeral_id,
     });
     DelegatorKeyGenStoreItem::save(&store_item)?;
 
@@ -8,6 +8,7 @@ pub const PROPERTY_RSA_PUB_KEY: &'static str = "rsa_pub_key";
 const PROPERTY_DELEGATOR_RA_TARGET_ROLE: &'static str = "delegator_ra_target_role";
 const VALUE_RA_TARGET_EXECUTOR: &'static str = "executor";
 const VALUE_RA_TARGET_INITIAL_PINNER: &'static str = "initial_pinner";
+const VALUE_RA_TARGET_PINNER: &'static str = "pinner";
 
 pub fn remote_attestation_executor(
     request: crate::p2p_proto::TaskKeyGenerationApplyRequst,
@@ -64,6 +65,18 @@ pub fn remote_attestation_initial_pinner(
     send_ra_request(peer_id, properties)
 }
 
+/// Properties to find pinners of existing key slices when resharing.
+pub fn generate_pinner_ra_properties(task_id: &str) -> HashMap<String, String> {
+    let mut properties: HashMap<String, String> = HashMap::new();
+    properties.insert(PROPERTY_TASK_ID.into(), task_id.into());
+    properties.insert(
+        PROPERTY_DELEGATOR_RA_TARGET_ROLE.into(),
+        VALUE_RA_TARGET_PINNER.into(),
+    );
+    tag_for_key_gen(&mut properties);
+    properties
+}
+
 pub fn is_executor_ra_response(item: &crate::actor_pinner_proto::ChallangeStoreItem) -> bool {
     item.properties
         .iter()
@@ -81,3 +94,12 @@ pub fn is_initial_pinner_ra_response(item: &crate::actor_pinner_proto::Challange
         .value
         == VALUE_RA_TARGET_INITIAL_PINNER
 }
+
+pub fn is_pinner_ra_response(item: &crate::actor_pinner_proto::ChallangeStoreItem) -> bool {
+    item.properties
+        .iter()
+        .find(|v| PROPERTY_DELEGATOR_RA_TARGET_ROLE.eq(&v.key))
+        .unwrap_or(&crate::actor_pinner_proto::PropertyKeyPair::default())
+        .value
+        == VALUE_RA_TARGET_PINNER
+}
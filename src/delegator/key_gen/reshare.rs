@@ -0,0 +1,434 @@
+use crate::common::{idempotency, retention::TaskKind, send_signed_message, ExecutionInfo};
+use crate::delegator::key_gen::{
+    invite_candidates,
+    ra::generate_pinner_ra_properties,
+    send_execution_request,
+    store_item::{DelegatorKeyGenStoreItem, StoreItemEvent, StoreItemState},
+};
+use crate::delegator::sign::{begin_find_pinners, get_asset_info};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tea_actor_utility::{
+    encode_protobuf,
+    ipfs_p2p::{close_p2p, response_ipfs_p2p, P2pReplyType},
+};
+
+/// Key slices of an existing asset collected from its pinners, they are re-encrypted for the
+/// elected executor so that P2 can be recovered and split again for new initial pinners.
+/// Old key slices are not revoked, they can still recover P2 with the old minimum recovery
+/// number.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReshareInfo {
+    pub multi_sig_account: Vec<u8>,
+    pub p2_public_key: Vec<u8>,
+    /// Minimum recovery number of the current key slices
+    pub k: u8,
+    key_slices: HashMap<String, Option<Vec<u8>>>,
+    deployment_candidates: HashMap<String, Vec<String>>,
+    /// Pinners that passed RA for each deployment, only they may return its key slice
+    #[serde(default)]
+    pinners: HashMap<String, Vec<String>>,
+}
+
+impl ReshareInfo {
+    pub fn new(
+        multi_sig_account: Vec<u8>,
+        p2_public_key: Vec<u8>,
+        k: u8,
+        deployment_ids: &[String],
+    ) -> Self {
+        ReshareInfo {
+            multi_sig_account,
+            p2_public_key,
+            k,
+            key_slices: deployment_ids.iter().map(|id| (id.clone(), None)).collect(),
+            deployment_candidates: deployment_ids
+                .iter()
+                .map(|id| (id.clone(), Vec::new()))
+                .collect(),
+            pinners: HashMap::new(),
+        }
+    }
+
+    pub fn ready(&self) -> bool {
+        self.key_slices.values().filter(|v| v.is_some()).count() >= self.k as usize
+    }
+
+    pub fn has_found_key_slice(&self, deployment_id: &str) -> bool {
+        match self.key_slices.get(deployment_id) {
+            Some(item) => item.is_some(),
+            None => false,
+        }
+    }
+
+    pub fn insert_deployment(&mut self, deployment_id: &str, peer_id: &str) -> anyhow::Result<()> {
+        self.deployment_candidates
+            .get_mut(deployment_id)
+            .ok_or(anyhow::anyhow!(
+                "{}:{} deployment_id {} not exists",
+                line!(),
+                file!(),
+                deployment_id
+            ))?
+            .push(peer_id.to_string());
+        self.record_pinner(deployment_id, peer_id)
+    }
+
+    /// Remembers `peer_id` as a pinner of `deployment_id` that the key slice may come from.
+    pub fn record_pinner(&mut self, deployment_id: &str, peer_id: &str) -> anyhow::Result<()> {
+        if !self.key_slices.contains_key(deployment_id) {
+            return Err(anyhow::anyhow!(
+                "{}:{} deployment_id {} not exists",
+                line!(),
+                file!(),
+                deployment_id
+            ));
+        }
+        self.pinners
+            .entry(deployment_id.to_string())
+            .or_insert_with(Vec::new)
+            .push(peer_id.to_string());
+        Ok(())
+    }
+
+    pub fn is_pinner_of(&self, deployment_id: &str, peer_id: &str) -> bool {
+        match self.pinners.get(deployment_id) {
+            Some(peers) => peers.iter().any(|v| v == peer_id),
+            None => false,
+        }
+    }
+
+    pub fn insert_key_slice(
+        &mut self,
+        deployment_id: &str,
+        encrypted_key_slice: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        *self
+            .key_slices
+            .get_mut(deployment_id)
+            .ok_or(anyhow::anyhow!(
+                "{}:{} deployment_id {} not exists",
+                line!(),
+                file!(),
+                deployment_id
+            ))? = Some(encrypted_key_slice);
+        Ok(())
+    }
+
+    pub fn pop_all_candidates(&mut self) -> HashMap<String, Vec<String>> {
+        self.deployment_candidates.drain().collect()
+    }
+
+    /// Resharing must keep P2 unchanged, otherwise the multi-sig account would change.
+    pub fn is_same_account(&self, p2_public_key: &[u8], multi_sig_account: &[u8]) -> bool {
+        self.p2_public_key.as_slice() == p2_public_key
+            && self.multi_sig_account.as_slice() == multi_sig_account
+    }
+
+    pub fn to_request(&self) -> crate::p2p_proto::ReshareKeySlices {
+        crate::p2p_proto::ReshareKeySlices {
+            multi_sig_account: self.multi_sig_account.clone(),
+            minimum_recovery_number: self.k as u32,
+            encrypted_key_slices: self.key_slices.values().filter_map(|v| v.clone()).collect(),
+        }
+    }
+}
+
+pub fn process_reshare_event(
+    res: crate::actor_delegate_proto::ReshareResponse,
+) -> anyhow::Result<()> {
+    super::super::verifier::try_to_be_delegator(
+        res.data_adhoc.delegator_tea_nonce_rsa_encryption.clone(),
+        res.data_adhoc.delegator_tea_nonce_hash.clone(),
+        move |nonce| {
+            trace!("i'm delegator, continue to reshare key slices");
+            let res = res.clone();
+            get_asset_info(res.multi_sig_account.clone(), move |asset_info| {
+                let mut item = DelegatorKeyGenStoreItem::try_from(res.clone())?;
+                item.nonce = nonce.clone();
+
+                let exec_info = match ExecutionInfo::try_from(&asset_info) {
+                    Ok(info) => info,
+                    Err(e) => {
+                        warn!(
+                            "reject reshare task {} of unknown asset, details: {}",
+                            &item.task_info.task_id, e
+                        );
+                        item.transition(StoreItemEvent::Failed, None)?;
+                        return DelegatorKeyGenStoreItem::save(&item);
+                    }
+                };
+                // pinners of threshold assets never hand out their key slices
+                if exec_info.sign_mode.is_threshold() {
+                    warn!(
+                        "reject reshare task {} of threshold sign asset",
+                        &item.task_info.task_id
+                    );
+                    item.transition(StoreItemEvent::Failed, None)?;
+                    return DelegatorKeyGenStoreItem::save(&item);
+                }
+                item.task_info.exec_info.task_type = exec_info.task_type;
+                item.p1_public_key = asset_info.p1.clone();
+                item.p3_public_key = if asset_info.p3.is_empty() {
+                    None
+                } else {
+                    Some(asset_info.p3.clone())
+                };
+                item.reshare = Some(ReshareInfo::new(
+                    res.multi_sig_account.clone(),
+                    asset_info.p2.clone(),
+                    exec_info.k,
+                    &asset_info.p2_deployment_ids,
+                ));
+                DelegatorKeyGenStoreItem::save(&item)?;
+
+                let properties = generate_pinner_ra_properties(&item.task_info.task_id);
+                for id in asset_info.p2_deployment_ids.iter() {
+                    begin_find_pinners(id.to_string(), properties.clone())?;
+                }
+                invite_candidates(&mut item)
+            })?;
+            Ok(())
+        },
+    )
+}
+
+pub fn is_reshare_task(task_id: &str) -> bool {
+    match DelegatorKeyGenStoreItem::get(task_id) {
+        Ok(item) => item.reshare.is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Executor should be elected before key slices are requested, because pinners encrypt their
+/// key slice with the RSA public key of the executor.
+pub fn request_key_slices(item: &mut DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
+    let candidates = match item.reshare.as_mut() {
+        Some(reshare) => reshare.pop_all_candidates(),
+        None => return Ok(()),
+    };
+    item.transition(StoreItemEvent::KeySlicesRequested, None)?;
+    DelegatorKeyGenStoreItem::save(item)?;
+
+    for (deployment_id, peers) in candidates {
+        for peer_id in peers {
+            send_get_pinner_key_slice_request(item, &peer_id, &deployment_id)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn on_pinner_ra_success(
+    task_id: &str,
+    peer_id: &str,
+    deployment_id: &str,
+) -> anyhow::Result<()> {
+    debug!(
+        "got reshare pinner ra success response, task id: {}, peer id: {}",
+        task_id, peer_id
+    );
+    let mut item = DelegatorKeyGenStoreItem::get(task_id)?;
+    let collecting = item.state == StoreItemState::CollectingKeySlices;
+    let executor_elected = item.executor.is_some();
+    let reshare = item.reshare.as_mut().ok_or(anyhow::anyhow!(
+        "{}:{} task {} is not a reshare task",
+        line!(),
+        file!(),
+        task_id
+    ))?;
+    if reshare.has_found_key_slice(deployment_id) {
+        info!(
+            "key_slice with deployment_id {} already exists, just ignore",
+            deployment_id
+        );
+        return Ok(());
+    }
+
+    if collecting {
+        reshare.record_pinner(deployment_id, peer_id)?;
+        DelegatorKeyGenStoreItem::save(&item)?;
+        return send_get_pinner_key_slice_request(&item, peer_id, deployment_id);
+    }
+    if executor_elected {
+        info!(
+            "reshare task {} needs no more key slices, just ignore",
+            task_id
+        );
+        return Ok(());
+    }
+    info!("executor not elected, deal later");
+    reshare.insert_deployment(deployment_id, peer_id)?;
+    DelegatorKeyGenStoreItem::save(&item)
+}
+
+pub fn process_pinner_key_slice_response(
+    res: crate::p2p_proto::TaskSignGetPinnerKeySliceResponse,
+    peer_id: &str,
+    reply_to: &str,
+) -> anyhow::Result<()> {
+    let key = idempotency::message_key(
+        "TaskSignGetPinnerKeySliceResponse",
+        peer_id,
+        &encode_protobuf(res.clone())?,
+    );
+    if !idempotency::claim(TaskKind::DelegatorKeyGen, &res.task_id, &key)? {
+        return idempotency::acknowledge_duplicate(reply_to, &res.task_id);
+    }
+    let result = collect_key_slice(&res, peer_id, reply_to, &key);
+    idempotency::release_on_error(TaskKind::DelegatorKeyGen, &res.task_id, &key, result)
+}
+
+fn collect_key_slice(
+    res: &crate::p2p_proto::TaskSignGetPinnerKeySliceResponse,
+    peer_id: &str,
+    reply_to: &str,
+    key: &str,
+) -> anyhow::Result<()> {
+    let mut item = DelegatorKeyGenStoreItem::get(&res.task_id)?;
+    let reshare = item.reshare.as_mut().ok_or(anyhow::anyhow!(
+        "{}:{} task {} is not a reshare task",
+        line!(),
+        file!(),
+        &res.task_id
+    ))?;
+    if !reshare.is_pinner_of(&res.deployment_id, peer_id) {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &res.task_id,
+            &format!("{} is not a pinner of {}", peer_id, &res.deployment_id),
+            P2pReplyType::Rejected,
+        );
+    }
+    if reshare.has_found_key_slice(&res.deployment_id) {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &res.task_id,
+            &format!("pinner of {} already exists", &res.deployment_id),
+            P2pReplyType::Rejected,
+        );
+    }
+    reshare.insert_key_slice(&res.deployment_id, res.encrypted_key_slice.clone())?;
+    let ready = reshare.ready();
+    DelegatorKeyGenStoreItem::save(&item)?;
+    idempotency::mark_processed(TaskKind::DelegatorKeyGen, &res.task_id, key)?;
+    close_p2p(peer_id).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    if item.state != StoreItemState::CollectingKeySlices || !ready {
+        return Ok(());
+    }
+    debug!(
+        "collected enough key slices, begin to reshare task {}",
+        &item.task_info.task_id
+    );
+    send_execution_request(&mut item)
+}
+
+fn send_get_pinner_key_slice_request(
+    item: &DelegatorKeyGenStoreItem,
+    peer_id: &str,
+    deployment_id: &str,
+) -> anyhow::Result<()> {
+    let executor = item.executor.as_ref().ok_or(anyhow::anyhow!(
+        "{}:{} failed to get executor, task id is {}",
+        line!(),
+        file!(),
+        &item.task_info.task_id
+    ))?;
+    debug!(
+        "begin to send get pinner key slice request to {}, task id is: {}",
+        peer_id, &item.task_info.task_id
+    );
+    send_signed_message(
+        peer_id,
+        &item.task_info.task_id,
+        crate::p2p_proto::GeneralMsg {
+            msg: Some(
+                crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceRequest(
+                    crate::p2p_proto::TaskSignGetPinnerKeySliceRequest {
+                        task_id: item.task_info.task_id.clone(),
+                        rsa_pub_key: executor.rsa_pub_key.clone(),
+                        executor_peer_id: executor.peer_id.clone(),
+                        deployment_id: deployment_id.to_string(),
+                        nonce_commit: None,
+                    },
+                ),
+            ),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_reshare(k: u8) -> ReshareInfo {
+        ReshareInfo::new(
+            vec![1],
+            vec![2],
+            k,
+            &["d1".to_string(), "d2".to_string(), "d3".to_string()],
+        )
+    }
+
+    #[test]
+    fn ready_after_k_key_slices() -> anyhow::Result<()> {
+        let mut reshare = new_reshare(2);
+        assert!(!reshare.ready());
+
+        reshare.insert_key_slice("d1", vec![1])?;
+        assert!(reshare.has_found_key_slice("d1"));
+        assert!(!reshare.has_found_key_slice("d2"));
+        assert!(!reshare.ready());
+
+        reshare.insert_key_slice("d3", vec![3])?;
+        assert!(reshare.ready());
+        assert_eq!(2, reshare.to_request().encrypted_key_slices.len());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_deployment_is_rejected() {
+        let mut reshare = new_reshare(2);
+        assert!(reshare.insert_key_slice("d4", vec![4]).is_err());
+        assert!(reshare.insert_deployment("d4", "peer").is_err());
+        assert!(!reshare.has_found_key_slice("d4"));
+    }
+
+    #[test]
+    fn candidates_are_popped_once() -> anyhow::Result<()> {
+        let mut reshare = new_reshare(2);
+        reshare.insert_deployment("d1", "peer1")?;
+        reshare.insert_deployment("d1", "peer2")?;
+
+        let candidates = reshare.pop_all_candidates();
+        assert_eq!(vec!["peer1", "peer2"], candidates["d1"]);
+        assert!(reshare.pop_all_candidates().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn only_recorded_pinners_return_key_slices() -> anyhow::Result<()> {
+        let mut reshare = new_reshare(2);
+        reshare.insert_deployment("d1", "peer1")?;
+        reshare.record_pinner("d2", "peer2")?;
+        reshare.pop_all_candidates();
+
+        assert!(reshare.is_pinner_of("d1", "peer1"));
+        assert!(reshare.is_pinner_of("d2", "peer2"));
+        assert!(!reshare.is_pinner_of("d1", "peer2"));
+        assert!(!reshare.is_pinner_of("d3", "peer1"));
+        assert!(reshare.record_pinner("d4", "peer1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn account_must_not_change() {
+        let reshare = new_reshare(2);
+        assert!(reshare.is_same_account(&[2], &[1]));
+        assert!(!reshare.is_same_account(&[3], &[1]));
+        assert!(!reshare.is_same_account(&[2], &[3]));
+    }
+}
@@ -1,7 +1,17 @@
 use crate::delegator::executor_info::ExecutorInfo;
 use crate::delegator::key_gen::initial_pinner_info::InitialPinnerInfo;
+use crate::delegator::key_gen::reshare::ReshareInfo;
 use crate::delegator::key_gen::{ExecutorRequestConstructor, TaskCandidates};
-use crate::{common::TaskInfo, BINDING_NAME};
+use crate::{
+    common::{
+        election::{elect_executor, ExecutorElection},
+        retention::{self, TaskKind},
+        state_machine::{self, StateMachine, Transition},
+        utils::current_timestamp,
+        TaskInfo,
+    },
+    BINDING_NAME,
+};
 use anyhow::anyhow;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
@@ -9,6 +19,7 @@ use tea_actor_utility::actor_kvp::{self, ShabbyLock};
 use tea_codec::error::TeaError;
 
 const PREFIX_DELEGATOR_TASK_KEY_GEN_STORE_ITEM: &'static str = "delegator_task_key_gen_store_item";
+const DELEGATOR_PENDING_KEY_GEN_TASKS: &'static str = "delegator_pending_key_gen_tasks";
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum StoreItemState {
@@ -16,10 +27,70 @@ pub enum StoreItemState {
     InvitedCandidates,
     RaBegun,
     RaCompleted,
+    CollectingKeySlices,
     SentToExecutor,
     ReceivedExecutionResult,
     SentToInitialPinner,
     ReceivedAllPinnerResponse,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum StoreItemEvent {
+    CandidatesInvited,
+    /// Executor of a reshare task is elected, key slices of the asset are requested from pinners
+    KeySlicesRequested,
+    ExecutionRequested,
+    ExecutionResultReceived,
+    KeySlicesShared,
+    AllPinnersResponded,
+    Failed,
+    Cancelled,
+}
+
+impl StoreItemState {
+    fn is_terminal(&self) -> bool {
+        match self {
+            StoreItemState::ReceivedAllPinnerResponse
+            | StoreItemState::Failed
+            | StoreItemState::Cancelled => true,
+            _ => false,
+        }
+    }
+}
+
+impl StateMachine for StoreItemState {
+    type Event = StoreItemEvent;
+
+    fn next(&self, event: &StoreItemEvent) -> Option<Self> {
+        match (self, event) {
+            (StoreItemState::Init, StoreItemEvent::CandidatesInvited) => {
+                Some(StoreItemState::InvitedCandidates)
+            }
+            (StoreItemState::InvitedCandidates, StoreItemEvent::KeySlicesRequested) => {
+                Some(StoreItemState::CollectingKeySlices)
+            }
+            (StoreItemState::InvitedCandidates, StoreItemEvent::ExecutionRequested)
+            | (StoreItemState::CollectingKeySlices, StoreItemEvent::ExecutionRequested) => {
+                Some(StoreItemState::SentToExecutor)
+            }
+            (StoreItemState::SentToExecutor, StoreItemEvent::ExecutionResultReceived) => {
+                Some(StoreItemState::ReceivedExecutionResult)
+            }
+            (StoreItemState::ReceivedExecutionResult, StoreItemEvent::KeySlicesShared) => {
+                Some(StoreItemState::SentToInitialPinner)
+            }
+            (StoreItemState::SentToInitialPinner, StoreItemEvent::AllPinnersResponded) => {
+                Some(StoreItemState::ReceivedAllPinnerResponse)
+            }
+            (state, StoreItemEvent::Failed) if !state.is_terminal() => Some(StoreItemState::Failed),
+            (state, StoreItemEvent::Cancelled) if !state.is_terminal() => {
+                Some(StoreItemState::Cancelled)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -28,28 +99,52 @@ pub struct DelegatorKeyGenStoreItem {
     pub task_info: TaskInfo,
     pub state: StoreItemState,
     pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub block_hash: Vec<u8>,
     pub executor: Option<ExecutorInfo>,
+    #[serde(default)]
+    pub executor_election: Option<ExecutorElection>,
     pub initial_pinners: Vec<InitialPinnerInfo>,
     pub p1_public_key: Vec<u8>,
     pub p2_public_key: Option<Vec<u8>>,
+    /// Optional user supplied cold recovery key, makes the account a 2-of-3 multisig
+    #[serde(default)]
+    pub p3_public_key: Option<Vec<u8>>,
     pub multi_sig_account: Option<Vec<u8>>,
     pub initial_pinner_responses: HashMap<String, Option<String>>,
+    /// Unix timestamp (in seconds) after which candidates election is forced
+    #[serde(default)]
+    pub deadline: Option<u64>,
+    /// Set if the task reshares key slices of an existing asset instead of generating a new key
+    #[serde(default)]
+    pub reshare: Option<ReshareInfo>,
+    #[serde(default)]
+    pub transitions: Vec<Transition<StoreItemState, StoreItemEvent>>,
     candidate_executors: Vec<ExecutorInfo>,
     candidate_initial_pinners: Vec<InitialPinnerInfo>,
 }
 
 impl TaskCandidates for DelegatorKeyGenStoreItem {
     fn ready(&self) -> bool {
-        // todo return true if timeout
-
-        // todo only if count of candidates over than specified number
-
         // this is essential condition to construct an execution request
         return !self.candidate_executors.is_empty()
             && (self.candidate_executors.len() + self.candidate_initial_pinners.len())
                 >= (self.task_info.exec_info.n + 1) as usize;
     }
 
+    fn is_timeout(&self, now: u64) -> bool {
+        match self.deadline {
+            Some(deadline) => now >= deadline,
+            None => false,
+        }
+    }
+
+    /// `n` will be shrunk to the available candidates, which must stay greater than `k`.
+    fn ready_on_timeout(&self) -> bool {
+        !self.candidate_executors.is_empty()
+            && self.available_initial_pinners_count() > self.task_info.exec_info.k as usize
+    }
+
     fn insert_executor(&mut self, executor: ExecutorInfo) {
         self.candidate_executors.push(executor);
     }
@@ -71,16 +166,60 @@ impl TryFrom<crate::actor_delegate_proto::KeyGenerationResponse> for DelegatorKe
         value: crate::actor_delegate_proto::KeyGenerationResponse,
     ) -> Result<Self, Self::Error> {
         let p1_public_key = value.p1_public_key.clone();
+        let p3_public_key = if value.p3_public_key.is_empty() {
+            None
+        } else {
+            Some(value.p3_public_key.clone())
+        };
+        if p3_public_key.as_ref() == Some(&p1_public_key) {
+            return Err(TeaError::CommonError(
+                "p3 public key should not be the same as p1 public key".into(),
+            ));
+        }
+        let block_hash = value.data_adhoc.block_hash.clone();
         Ok(DelegatorKeyGenStoreItem {
             task_info: TaskInfo::try_from(value)?,
             state: StoreItemState::Init,
             nonce: Vec::new(),
+            block_hash,
             executor: None,
+            executor_election: None,
             p1_public_key,
             p2_public_key: None,
+            p3_public_key,
+            multi_sig_account: None,
+            initial_pinners: Vec::new(),
+            initial_pinner_responses: HashMap::new(),
+            deadline: None,
+            reshare: None,
+            transitions: Vec::new(),
+            candidate_executors: Vec::new(),
+            candidate_initial_pinners: Vec::new(),
+        })
+    }
+}
+
+impl TryFrom<crate::actor_delegate_proto::ReshareResponse> for DelegatorKeyGenStoreItem {
+    type Error = TeaError;
+
+    fn try_from(value: crate::actor_delegate_proto::ReshareResponse) -> Result<Self, Self::Error> {
+        let block_hash = value.data_adhoc.block_hash.clone();
+        Ok(DelegatorKeyGenStoreItem {
+            task_info: TaskInfo::try_from(value)?,
+            state: StoreItemState::Init,
+            nonce: Vec::new(),
+            block_hash,
+            executor: None,
+            executor_election: None,
+            p1_public_key: Vec::new(),
+            p2_public_key: None,
+            p3_public_key: None,
             multi_sig_account: None,
             initial_pinners: Vec::new(),
             initial_pinner_responses: HashMap::new(),
+            deadline: None,
+            reshare: None,
+            transitions: Vec::new(),
             candidate_executors: Vec::new(),
             candidate_initial_pinners: Vec::new(),
         })
@@ -102,6 +241,7 @@ impl TryInto<crate::actor_delegate_proto::UpdateKeyGenerationResult> for Delegat
             public_key: self
                 .p2_public_key
                 .ok_or(anyhow::anyhow!("public key is empty"))?,
+            p3_public_key: self.p3_public_key.unwrap_or_default(),
             deployment_ids: self
                 .initial_pinner_responses
                 .into_iter()
@@ -112,6 +252,26 @@ impl TryInto<crate::actor_delegate_proto::UpdateKeyGenerationResult> for Delegat
     }
 }
 
+impl TryInto<crate::actor_delegate_proto::UpdateReshareResult> for DelegatorKeyGenStoreItem {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<crate::actor_delegate_proto::UpdateReshareResult, Self::Error> {
+        let reshare = self
+            .reshare
+            .ok_or(anyhow::anyhow!("task is not a reshare task"))?;
+        Ok(crate::actor_delegate_proto::UpdateReshareResult {
+            task_id: base64::decode(self.task_info.task_id)?,
+            delegator_nonce: self.nonce,
+            multi_sig_account: reshare.multi_sig_account,
+            deployment_ids: self
+                .initial_pinner_responses
+                .into_iter()
+                .filter_map(|(_, v)| v)
+                .collect(),
+        })
+    }
+}
+
 impl ExecutorRequestConstructor for DelegatorKeyGenStoreItem {
     fn ready(&self) -> bool {
         return !self.executor.is_none()
@@ -132,17 +292,28 @@ impl ExecutorRequestConstructor for DelegatorKeyGenStoreItem {
             minimum_recovery_number: self.task_info.exec_info.k as u32,
             key_type: self.task_info.exec_info.task_type.clone(),
             p1_public_key: self.p1_public_key.clone(),
+            p3_public_key: self.p3_public_key.clone().unwrap_or_default(),
+            reshare: self.reshare.as_ref().map(|v| v.to_request()),
         })
     }
 }
 
 impl DelegatorKeyGenStoreItem {
     pub fn get(task_id: &str) -> anyhow::Result<Self> {
-        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
-        actor_kvp::get::<DelegatorKeyGenStoreItem>(BINDING_NAME, &get_task_store_item_key(task_id))?
+        DelegatorKeyGenStoreItem::find(task_id)?
             .ok_or(TeaError::CommonError(format!("can not find task {}", task_id)).into())
     }
 
+    /// `None` if I'm not delegator of the key generation task, e.g. the task is a sign task or
+    /// has been archived.
+    pub fn find(task_id: &str) -> anyhow::Result<Option<Self>> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(actor_kvp::get::<DelegatorKeyGenStoreItem>(
+            BINDING_NAME,
+            &get_task_store_item_key(task_id),
+        )?)
+    }
+
     pub fn save(item: &DelegatorKeyGenStoreItem) -> anyhow::Result<()> {
         let _lock = ShabbyLock::lock(BINDING_NAME, &item.task_info.task_id);
         actor_kvp::set_forever(
@@ -150,9 +321,84 @@ impl DelegatorKeyGenStoreItem {
             &get_task_store_item_key(&item.task_info.task_id),
             item,
         )?;
+        retention::touch(TaskKind::DelegatorKeyGen, &item.task_info.task_id)?;
+        if item.is_terminal() {
+            retention::mark_finished(
+                TaskKind::DelegatorKeyGen,
+                &item.task_info.task_id,
+                &item.state,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(task_id: &str) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        actor_kvp::del(BINDING_NAME, &get_task_store_item_key(task_id))?;
         Ok(())
     }
 
+    pub fn is_terminal(&self) -> bool {
+        self.state.is_terminal()
+    }
+
+    /// Moves the key generation task by `event`, `peer_id` is the elected executor or initial
+    /// pinner whose response caused it if any. Timeouts, layer1 and local events have no peer.
+    pub fn transition(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.transition_at(event, peer_id, current_timestamp()?)
+    }
+
+    pub fn transition_at(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        state_machine::transit(
+            &self.task_info.task_id,
+            &mut self.state,
+            &mut self.transitions,
+            event,
+            peer_id,
+            timestamp,
+        )
+    }
+
+    pub fn is_executor(&self, peer_id: &str) -> bool {
+        match self.executor.as_ref() {
+            Some(executor) => executor.peer_id == peer_id,
+            None => false,
+        }
+    }
+
+    pub fn is_initial_pinner(&self, peer_id: &str) -> bool {
+        self.initial_pinners.iter().any(|v| v.peer_id == peer_id)
+    }
+
+    /// Peers that may hold store items or RSA keys of the task, including candidates that
+    /// were not elected.
+    pub fn participant_peer_ids(&self) -> Vec<String> {
+        let mut peer_ids: Vec<String> = Vec::new();
+        let executors = self.executor.iter().chain(self.candidate_executors.iter());
+        let pinners = self
+            .initial_pinners
+            .iter()
+            .chain(self.candidate_initial_pinners.iter());
+        for peer_id in executors
+            .map(|v| &v.peer_id)
+            .chain(pinners.map(|v| &v.peer_id))
+        {
+            if !peer_ids.contains(peer_id) {
+                peer_ids.push(peer_id.clone());
+            }
+        }
+        peer_ids
+    }
+
     pub fn is_all_initial_pinners_ready(&self) -> bool {
         for (_, v) in self.initial_pinner_responses.iter() {
             if v.is_none() {
@@ -162,14 +408,45 @@ impl DelegatorKeyGenStoreItem {
         true
     }
 
+    /// Shrink `n` to the number of candidates we have, so that `elect` can run after timeout.
+    pub fn shrink_to_available_candidates(&mut self) {
+        let available = self.available_initial_pinners_count();
+        if available < self.task_info.exec_info.n as usize {
+            info!(
+                "task {} only has {} initial pinner candidates, shrink n from {}",
+                &self.task_info.task_id, available, self.task_info.exec_info.n
+            );
+            self.task_info.exec_info.n = available as u8;
+        }
+    }
+
+    fn available_initial_pinners_count(&self) -> usize {
+        // one of candidate executors will be elected as executor, the rest can be initial pinners
+        self.candidate_initial_pinners.len() + self.candidate_executors.len().saturating_sub(1)
+    }
+
     fn select_executor(&mut self) -> anyhow::Result<()> {
-        // todo: min XOR value calculated by `block hash + task hash + candidate ephemeral id`
-        //  of all candidates should be executor
-        self.executor = Some(self.candidate_executors.pop().ok_or(anyhow!(
-            "{}:{} candidate executor can not be empty",
-            line!(),
-            file!(),
-        ))?);
+        let candidates: Vec<Vec<u8>> = self
+            .candidate_executors
+            .iter()
+            .map(|v| v.ephemeral_id.clone())
+            .collect();
+        let index = elect_executor(&self.block_hash, &self.task_info.task_id, &candidates).ok_or(
+            anyhow!(
+                "{}:{} candidate executor can not be empty",
+                line!(),
+                file!(),
+            ),
+        )?;
+
+        let executor = self.candidate_executors.remove(index);
+        self.executor_election = Some(ExecutorElection {
+            block_hash: self.block_hash.clone(),
+            task_id: self.task_info.task_id.clone(),
+            candidates,
+            executor: executor.ephemeral_id.clone(),
+        });
+        self.executor = Some(executor);
         Ok(())
     }
 
@@ -184,14 +461,285 @@ impl DelegatorKeyGenStoreItem {
                 continue;
             }
 
-            if let Some(e) = self.candidate_executors.pop() {
-                self.initial_pinners.push(e.into());
+            match self.candidate_executors.pop() {
+                Some(e) => self.initial_pinners.push(e.into()),
+                None => break,
             }
         }
         Ok(())
     }
 }
 
+pub fn pending_task_ids() -> anyhow::Result<Vec<String>> {
+    let _lock = ShabbyLock::lock(BINDING_NAME, DELEGATOR_PENDING_KEY_GEN_TASKS);
+    Ok(
+        actor_kvp::get::<Vec<String>>(BINDING_NAME, DELEGATOR_PENDING_KEY_GEN_TASKS)?
+            .unwrap_or_default(),
+    )
+}
+
+pub fn add_pending_task(task_id: &str) -> anyhow::Result<()> {
+    update_pending_tasks(|ids| {
+        if !ids.iter().any(|v| v == task_id) {
+            ids.push(task_id.to_string());
+        }
+    })
+}
+
+pub fn remove_pending_task(task_id: &str) -> anyhow::Result<()> {
+    update_pending_tasks(|ids| ids.retain(|v| v != task_id))
+}
+
+fn update_pending_tasks<F>(update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<String>),
+{
+    let _lock = ShabbyLock::lock(BINDING_NAME, DELEGATOR_PENDING_KEY_GEN_TASKS);
+    let mut ids = actor_kvp::get::<Vec<String>>(BINDING_NAME, DELEGATOR_PENDING_KEY_GEN_TASKS)?
+        .unwrap_or_default();
+    update(&mut ids);
+    actor_kvp::set_forever(BINDING_NAME, DELEGATOR_PENDING_KEY_GEN_TASKS, &ids)?;
+    Ok(())
+}
+
 fn get_task_store_item_key(task_id: &str) -> String {
     format!("{}_{}", PREFIX_DELEGATOR_TASK_KEY_GEN_STORE_ITEM, task_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::state_machine::check_transition_table;
+    use crate::common::{ExecutionInfo, SignMode, TaskPayment};
+
+    fn new_item(n: u8, k: u8) -> DelegatorKeyGenStoreItem {
+        DelegatorKeyGenStoreItem {
+            task_info: TaskInfo {
+                task_id: "task".into(),
+                exec_info: ExecutionInfo {
+                    n,
+                    k,
+                    task_type: "bitcoin_mainnet".into(),
+                    sign_mode: SignMode::Recover,
+                },
+                payment: TaskPayment::default(),
+            },
+            state: StoreItemState::InvitedCandidates,
+            nonce: Vec::new(),
+            block_hash: vec![1u8; 32],
+            executor: None,
+            executor_election: None,
+            initial_pinners: Vec::new(),
+            p1_public_key: Vec::new(),
+            p2_public_key: None,
+            p3_public_key: None,
+            multi_sig_account: None,
+            initial_pinner_responses: HashMap::new(),
+            deadline: Some(100),
+            reshare: None,
+            transitions: Vec::new(),
+            candidate_executors: Vec::new(),
+            candidate_initial_pinners: Vec::new(),
+        }
+    }
+
+    fn executor(id: &str) -> ExecutorInfo {
+        ExecutorInfo {
+            peer_id: id.into(),
+            rsa_pub_key: Vec::new(),
+            ephemeral_id: id.as_bytes().to_vec(),
+        }
+    }
+
+    fn pinner(id: &str) -> InitialPinnerInfo {
+        InitialPinnerInfo {
+            peer_id: id.into(),
+            rsa_pub_key: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn timeout_follows_deadline() {
+        let mut item = new_item(3, 2);
+        assert!(!item.is_timeout(99));
+        assert!(item.is_timeout(100));
+        assert!(item.is_timeout(101));
+
+        item.deadline = None;
+        assert!(!item.is_timeout(u64::MAX));
+    }
+
+    #[test]
+    fn elect_with_available_candidates_after_timeout() -> anyhow::Result<()> {
+        let mut item = new_item(4, 2);
+        item.insert_executor(executor("e1"));
+        item.insert_initial_pinner(pinner("p1"));
+        assert!(!(&item as &dyn TaskCandidates).ready());
+        assert!(!item.ready_on_timeout());
+
+        // n == k would break the k < n invariant
+        item.insert_initial_pinner(pinner("p2"));
+        assert!(!item.ready_on_timeout());
+
+        item.insert_initial_pinner(pinner("p3"));
+        assert!(!(&item as &dyn TaskCandidates).ready());
+        assert!(item.ready_on_timeout());
+
+        item.shrink_to_available_candidates();
+        assert_eq!(3, item.task_info.exec_info.n);
+        item.elect()?;
+        assert_eq!("e1", item.executor.as_ref().unwrap().peer_id);
+        assert_eq!(3, item.initial_pinners.len());
+        assert!((&item as &dyn ExecutorRequestConstructor).ready());
+        Ok(())
+    }
+
+    #[test]
+    fn spare_executors_count_as_initial_pinners_after_timeout() {
+        let mut item = new_item(3, 2);
+        item.insert_executor(executor("e1"));
+        assert!(!item.ready_on_timeout());
+
+        item.insert_executor(executor("e2"));
+        item.insert_executor(executor("e3"));
+        assert!(!item.ready_on_timeout());
+
+        item.insert_executor(executor("e4"));
+        assert!(item.ready_on_timeout());
+    }
+
+    #[test]
+    fn not_ready_on_timeout_without_executor() {
+        let mut item = new_item(3, 2);
+        item.insert_initial_pinner(pinner("p1"));
+        item.insert_initial_pinner(pinner("p2"));
+        item.insert_initial_pinner(pinner("p3"));
+        assert!(!item.ready_on_timeout());
+    }
+
+    #[test]
+    fn participants_include_candidates() -> anyhow::Result<()> {
+        let mut item = new_item(2, 1);
+        item.insert_executor(executor("e1"));
+        item.insert_executor(executor("e2"));
+        item.insert_initial_pinner(pinner("p1"));
+        assert_eq!(vec!["e1", "e2", "p1"], item.participant_peer_ids());
+
+        item.elect()?;
+        let mut peer_ids = item.participant_peer_ids();
+        peer_ids.sort();
+        assert_eq!(vec!["e1", "e2", "p1"], peer_ids);
+        Ok(())
+    }
+
+    #[test]
+    fn only_elected_peers_can_respond() -> anyhow::Result<()> {
+        let mut item = new_item(2, 1);
+        item.insert_executor(executor("e1"));
+        item.insert_executor(executor("e2"));
+        item.insert_initial_pinner(pinner("p1"));
+        assert!(!item.is_executor("e1"));
+        assert!(!item.is_initial_pinner("p1"));
+
+        item.elect()?;
+        let executor_peer_id = item.executor.as_ref().unwrap().peer_id.clone();
+        assert!(item.is_executor(&executor_peer_id));
+        assert!(!item.is_initial_pinner(&executor_peer_id));
+        assert!(item.is_initial_pinner("p1"));
+        assert!(!item.is_executor("p1"));
+        assert!(!item.is_initial_pinner("p2"));
+        Ok(())
+    }
+
+    #[test]
+    fn elect_executor_verifiably() -> anyhow::Result<()> {
+        let mut item = new_item(2, 1);
+        item.insert_executor(executor("e1"));
+        item.insert_executor(executor("e2"));
+        item.insert_executor(executor("e3"));
+        item.elect()?;
+
+        let election = item.executor_election.as_ref().unwrap();
+        assert!(election.verify());
+        assert_eq!(3, election.candidates.len());
+        assert_eq!(
+            election.executor,
+            item.executor.as_ref().unwrap().ephemeral_id
+        );
+        assert!(!item
+            .initial_pinners
+            .iter()
+            .any(|v| v.peer_id == item.executor.as_ref().unwrap().peer_id));
+        Ok(())
+    }
+
+    #[test]
+    fn transition_table() {
+        use super::StoreItemEvent as E;
+        use super::StoreItemState as S;
+        let unfinished = [
+            S::Init,
+            S::InvitedCandidates,
+            S::RaBegun,
+            S::RaCompleted,
+            S::CollectingKeySlices,
+            S::SentToExecutor,
+            S::ReceivedExecutionResult,
+            S::SentToInitialPinner,
+        ];
+        let mut table = vec![
+            (S::Init, E::CandidatesInvited, S::InvitedCandidates),
+            // only reshare tasks collect key slices before execution
+            (
+                S::InvitedCandidates,
+                E::KeySlicesRequested,
+                S::CollectingKeySlices,
+            ),
+            (
+                S::InvitedCandidates,
+                E::ExecutionRequested,
+                S::SentToExecutor,
+            ),
+            (
+                S::CollectingKeySlices,
+                E::ExecutionRequested,
+                S::SentToExecutor,
+            ),
+            (
+                S::SentToExecutor,
+                E::ExecutionResultReceived,
+                S::ReceivedExecutionResult,
+            ),
+            (
+                S::ReceivedExecutionResult,
+                E::KeySlicesShared,
+                S::SentToInitialPinner,
+            ),
+            (
+                S::SentToInitialPinner,
+                E::AllPinnersResponded,
+                S::ReceivedAllPinnerResponse,
+            ),
+        ];
+        for state in unfinished.iter() {
+            table.push((state.clone(), E::Failed, S::Failed));
+            table.push((state.clone(), E::Cancelled, S::Cancelled));
+        }
+        let mut states = unfinished.to_vec();
+        states.extend(vec![S::ReceivedAllPinnerResponse, S::Failed, S::Cancelled]);
+        check_transition_table(
+            &states,
+            &[
+                E::CandidatesInvited,
+                E::KeySlicesRequested,
+                E::ExecutionRequested,
+                E::ExecutionResultReceived,
+                E::KeySlicesShared,
+                E::AllPinnersResponded,
+                E::Failed,
+                E::Cancelled,
+            ],
+            &table,
+        );
+    }
+}
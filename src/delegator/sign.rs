@@ -1,20 +1,25 @@
 use crate::common::{
+    send_signed_message,
     utils::{from_hash_map, invite_candidate_executors},
-    ExecutionInfo,
+    ExecutionInfo, TaskInfo,
 };
-use crate::delegator::sign::store_item::KeySliceInfo;
+use crate::delegator::sign::store_item::{KeySliceInfo, NonceCommitment};
 use prost::Message;
-use std::{collections::HashMap, convert::TryFrom};
-use store_item::{DelegatorSignStoreItem, StoreItemState};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+use store_item::{DelegatorSignStoreItem, StoreItemEvent, StoreItemState};
 use tea_actor_utility::{
     action,
     actor_nats::response_reply_with_subject,
     encode_protobuf,
-    ipfs_p2p::{response_ipfs_p2p, send_message, P2pReplyType},
+    ipfs_p2p::{response_ipfs_p2p, P2pReplyType},
 };
 use wascc_actor::prelude::codec::messaging::BrokerMessage;
 use wascc_actor::HandlerResult;
 
+mod broadcaster;
 mod observers;
 mod ra;
 mod store_item;
@@ -30,41 +35,56 @@ pub fn process_sign_with_key_slices_event(
         res.data_adhoc.delegator_tea_nonce_hash.clone(),
         move |nonce| {
             let res = res.clone();
-            get_deployment_ids(res.multi_sig_account.clone(), move |deployment_ids| {
+            get_asset_info(res.multi_sig_account.clone(), move |asset_info| {
                 let mut item = DelegatorSignStoreItem::try_from(res.clone())?;
                 item.nonce = nonce.clone();
-                // todo query p1 public key from layer1 by item.multi_sig_account, and verify item.p1_signature
-                // todo query n,k,keyType and deployment ids from layer1 by item.multi_sig_account
-                let n = 2;
-                let k = 1;
-                let task_type = "bitcoin_mainnet".to_string();
+
+                let exec_info = match ExecutionInfo::try_from(&asset_info) {
+                    Ok(info) => info,
+                    Err(e) => {
+                        warn!(
+                            "reject sign task {} of unknown asset, details: {}",
+                            &item.task_info.task_id, e
+                        );
+                        item.transition(StoreItemEvent::Rejected, None)?;
+                        return DelegatorSignStoreItem::save(&item);
+                    }
+                };
+                let deployment_ids = asset_info.p2_deployment_ids.clone();
 
                 let properties = ra::generate_pinner_ra_properties(&item.task_info.task_id);
-                item.task_info.exec_info = ExecutionInfo { n, k, task_type };
+                item.task_info.exec_info = exec_info;
+                item.p1_public_key = asset_info.p1.clone();
+                item.p2_public_key = asset_info.p2.clone();
+                item.p3_public_key = match asset_info.p3.is_empty() {
+                    true => None,
+                    false => Some(asset_info.p3.clone()),
+                };
+                if let Err(e) = item.public_keys() {
+                    warn!(
+                        "reject sign task {} of asset without public keys, details: {}",
+                        &item.task_info.task_id, e
+                    );
+                    item.transition(StoreItemEvent::Rejected, None)?;
+                    return DelegatorSignStoreItem::save(&item);
+                }
+                if !item.verify_p1_signature()? {
+                    warn!(
+                        "reject sign task {} because p1 signature is invalid",
+                        &item.task_info.task_id
+                    );
+                    item.transition(StoreItemEvent::Rejected, None)?;
+                    return DelegatorSignStoreItem::save(&item);
+                }
                 item.init_deployment_resources(&deployment_ids);
-                item.state = StoreItemState::Initialized;
+                item.transition(StoreItemEvent::Initialized, None)?;
                 DelegatorSignStoreItem::save(&item)?;
 
+                let multi_sig_account = item.multi_sig_account.clone();
                 invite_candidate_executors(
                     item.task_info.clone(),
                     move |task_info, peer_id| {
-                        send_message(
-                            &peer_id,
-                            &task_info.task_id,
-                            crate::p2p_proto::GeneralMsg {
-                                msg: Some(
-                                    crate::p2p_proto::general_msg::Msg::SignCandidateRequest(
-                                        crate::p2p_proto::SignCandidateRequest {
-                                            task_id: task_info.task_id.clone(),
-                                            multi_sig_account: item.multi_sig_account.clone(),
-                                            n: task_info.exec_info.n as u32,
-                                            k: task_info.exec_info.k as u32,
-                                            task_type: task_info.exec_info.task_type.clone(),
-                                        },
-                                    ),
-                                ),
-                            },
-                        )
+                        send_sign_candidate_request(&peer_id, &task_info, &multi_sig_account)
                     },
                     move |task_info, _| {
                         for id in deployment_ids.iter() {
@@ -72,7 +92,7 @@ pub fn process_sign_with_key_slices_event(
                         }
 
                         let mut item = DelegatorSignStoreItem::get(&task_info.task_id)?;
-                        item.state = StoreItemState::FindingDeployments;
+                        item.transition(StoreItemEvent::ExecutorsInvited, None)?;
                         DelegatorSignStoreItem::save(&item)?;
                         Ok(())
                     },
@@ -83,18 +103,40 @@ pub fn process_sign_with_key_slices_event(
     )
 }
 
-fn get_deployment_ids<F>(multi_sig_account: Vec<u8>, mut callback: F) -> HandlerResult<()>
+fn send_sign_candidate_request(
+    peer_id: &str,
+    task_info: &TaskInfo,
+    multi_sig_account: &[u8],
+) -> anyhow::Result<()> {
+    send_signed_message(
+        peer_id,
+        &task_info.task_id,
+        crate::p2p_proto::GeneralMsg {
+            msg: Some(crate::p2p_proto::general_msg::Msg::SignCandidateRequest(
+                crate::p2p_proto::SignCandidateRequest {
+                    task_id: task_info.task_id.clone(),
+                    multi_sig_account: multi_sig_account.to_vec(),
+                    n: task_info.exec_info.n as u32,
+                    k: task_info.exec_info.k as u32,
+                    task_type: task_info.exec_info.task_type.clone(),
+                    threshold_sign: task_info.exec_info.sign_mode.is_threshold(),
+                    executor_fee: task_info.payment.executor_fee,
+                },
+            )),
+        },
+    )
+}
+
+pub fn get_asset_info<F>(multi_sig_account: Vec<u8>, mut callback: F) -> HandlerResult<()>
 where
-    F: FnMut(Vec<String>) -> anyhow::Result<()> + Send + Sync + 'static,
+    F: FnMut(crate::actor_delegate_proto::AssetInfo) -> anyhow::Result<()> + Send + Sync + 'static,
 {
     #[cfg(feature = "dev")]
     {
-        let deployment_ids = super::dump_methods::get_deployment_ids()?;
-        debug!("get mocked deployment_ids: {:?}", &deployment_ids);
-        if let Some(deployment_ids) = deployment_ids {
-            if deployment_ids.len() > 0 {
-                return Ok(callback(deployment_ids)?);
-            }
+        let asset_info = super::dump_methods::get_asset_info()?;
+        debug!("get mocked asset_info: {:?}", &asset_info);
+        if let Some(asset_info) = asset_info {
+            return Ok(callback(asset_info)?);
         }
     }
 
@@ -115,12 +157,43 @@ where
                 "request for get_deployment_ids from layer1 got response: {:?}",
                 &get_deployments_res
             );
-            Ok(callback(get_deployments_res.asset_info.p2_deployment_ids)?)
+            Ok(callback(get_deployments_res.asset_info)?)
         },
     )
 }
 
-fn begin_find_pinners(
+/// Sign task as recorded by layer1, together with the peer layer1 chose as its delegator.
+pub fn get_sign_task<F>(task_id: &str, mut callback: F) -> HandlerResult<()>
+where
+    F: FnMut(crate::actor_delegate_proto::GetSignTaskResponse) -> anyhow::Result<()>
+        + Send
+        + Sync
+        + 'static,
+{
+    let content = base64::encode(&encode_protobuf(
+        crate::actor_delegate_proto::GetSignTask {
+            task_id: base64::decode(task_id)?,
+        },
+    )?);
+    action::call(
+        "layer1.async.reply.get_sign_task",
+        "actor.gluon.inbox",
+        content.into(),
+        move |msg| {
+            let base64_decoded_msg_body = base64::decode(String::from_utf8(msg.body.clone())?)?;
+            let get_sign_task_res = crate::actor_delegate_proto::GetSignTaskResponse::decode(
+                base64_decoded_msg_body.as_slice(),
+            )?;
+            debug!(
+                "request for get_sign_task from layer1 got response: {:?}",
+                &get_sign_task_res
+            );
+            Ok(callback(get_sign_task_res)?)
+        },
+    )
+}
+
+pub fn begin_find_pinners(
     deployment_id: String,
     properties: HashMap<String, String>,
 ) -> anyhow::Result<()> {
@@ -154,6 +227,24 @@ pub fn process_executor_sign_with_key_slices_request(
     reply_to: &str,
 ) -> anyhow::Result<()> {
     let item = DelegatorSignStoreItem::get(&req.task_id)?;
+    if item.state == StoreItemState::Cancelled {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &req.task_id,
+            "task has been cancelled".into(),
+            P2pReplyType::Rejected,
+        );
+    }
+    if item.is_blacklisted_executor(peer_id) {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &req.task_id,
+            "executor has been blacklisted".into(),
+            P2pReplyType::Rejected,
+        );
+    }
     if item.executor.is_some() {
         return response_ipfs_p2p(
             reply_to,
@@ -175,23 +266,174 @@ pub fn process_executor_sign_with_key_slices_request(
 pub fn process_commit_sign_result_request(
     req: crate::p2p_proto::TaskCommitSignResultRequest,
     peer_id: &str,
-    _reply_to: &str,
+    reply_to: &str,
 ) -> anyhow::Result<()> {
     debug!("process_commit_sign_result_request req: {:?}", &req);
     let mut item = DelegatorSignStoreItem::get(&req.task_id)?;
+    if item.state == StoreItemState::Cancelled {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &req.task_id,
+            "task has been cancelled".into(),
+            P2pReplyType::Rejected,
+        );
+    }
+    if !item.is_executor(peer_id) {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &req.task_id,
+            "only executor of the task can commit sign result".into(),
+            P2pReplyType::Rejected,
+        );
+    }
 
-    // todo verify signature using item.multi_sig_account
-
-    // todo commit task_id and req.witness hash into layer1
+    if item.state == StoreItemState::SentToExecutor {
+        if !item.verify_witness(&req.witness)? {
+            return reject_witness(item, peer_id);
+        }
+        // the witness is kept before broadcasting, so that a resent commit never broadcasts
+        // again and a failed layer1 update can be retried with it
+        item.witness = Some(req.witness.clone());
+        item.transition(StoreItemEvent::WitnessAccepted, Some(peer_id))?;
+        DelegatorSignStoreItem::save(&item)?;
+    }
+    if item.state != StoreItemState::Broadcasting {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &req.task_id,
+            "sign result of the task has been committed".into(),
+            P2pReplyType::Rejected,
+        );
+    }
 
-    // todo send transaction to bitcoin network (or other network decided by item.task_info.exec_info.task_type)
+    if item.transaction_id.is_none() {
+        let witness = item.witness.clone().ok_or(anyhow::anyhow!(
+            "{}:{} witness is empty",
+            line!(),
+            file!()
+        ))?;
+        let broadcaster = broadcaster::broadcaster_for(&item.task_info.exec_info.task_type)?;
+        item.transaction_id = Some(broadcaster::broadcast_signed_transaction(
+            broadcaster.as_ref(),
+            &item.transaction_data,
+            &witness,
+        )?);
+        DelegatorSignStoreItem::save(&item)?;
+    }
+    update_sign_result(item, peer_id)
+}
 
-    item.state = StoreItemState::CommitResult;
+/// Blacklists the executor that committed an invalid witness, and elects another one unless
+/// too many executors failed already.
+fn reject_witness(mut item: DelegatorSignStoreItem, peer_id: &str) -> anyhow::Result<()> {
+    let task_id = item.task_info.task_id.clone();
+    item.blacklist_executor();
+    if !item.can_reelect_executor() {
+        warn!(
+            "witness of task {} committed by {} is invalid, reject the task because {} executors failed",
+            &task_id,
+            peer_id,
+            item.blacklisted_executors.len()
+        );
+        item.transition(StoreItemEvent::Rejected, Some(peer_id))?;
+        DelegatorSignStoreItem::save(&item)?;
+        return close_p2p(peer_id).map_err(|e| anyhow::anyhow!("{}", e));
+    }
+    warn!(
+        "witness of task {} committed by {} is invalid, reopen executor selection",
+        &task_id, peer_id
+    );
+    item.transition(StoreItemEvent::WitnessRejected, Some(peer_id))?;
     DelegatorSignStoreItem::save(&item)?;
+    close_p2p(peer_id).map_err(|e| anyhow::anyhow!("{}", e))?;
 
-    info!("commit sign task successfully");
+    let task_info = item.task_info.clone();
+    let multi_sig_account = item.multi_sig_account.clone();
+    invite_candidate_executors(
+        item.task_info.clone(),
+        move |task_info, peer_id| {
+            send_sign_candidate_request(&peer_id, &task_info, &multi_sig_account)
+        },
+        move |_, _| {
+            debug!("reinvited executors of task {}", &task_info.task_id);
+            Ok(())
+        },
+    )
+}
 
-    close_p2p(peer_id).map_err(|e| anyhow::anyhow!("{}", e))?;
+/// Reports the broadcasted transaction to layer1, the task is committed only when layer1 has
+/// accepted it. A failed update leaves the task broadcasting, for the executor to commit again.
+fn update_sign_result(item: DelegatorSignStoreItem, peer_id: &str) -> anyhow::Result<()> {
+    let result: crate::actor_delegate_proto::UpdateSignResult = item.clone().try_into()?;
+    let task_id = item.task_info.task_id.clone();
+    let executor_peer_id = peer_id.to_string();
+    action::call(
+        "layer1.async.reply.update_sign_result",
+        "actor.gluon.inbox",
+        base64::encode(&encode_protobuf(result)?).into(),
+        move |msg| {
+            debug!("update_sign_result got response: {:?}", msg);
+            let mut item = DelegatorSignStoreItem::get(&task_id)?;
+            item.transition(StoreItemEvent::ResultCommitted, Some(&executor_peer_id))?;
+            DelegatorSignStoreItem::save(&item)?;
+            info!("commit sign task {} successfully", &task_id);
+            close_p2p_connections(&task_id)
+        },
+    )
+    .map_err(|e| {
+        warn!(
+            "failed to update sign result of task {} to layer1: {}",
+            &item.task_info.task_id, e
+        );
+        anyhow::anyhow!("{}", e)
+    })
+}
+
+pub fn delete_store_item(task_id: &str) -> anyhow::Result<()> {
+    DelegatorSignStoreItem::delete(task_id)
+}
+
+pub fn task_state(task_id: &str) -> anyhow::Result<Option<String>> {
+    Ok(DelegatorSignStoreItem::find(task_id)?.map(|item| format!("{:?}", item.state)))
+}
+
+/// Returns peers to be told to tear down the task, finished tasks are left untouched. `None` if
+/// i have no such task as delegator.
+pub fn cancel_task(task_id: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let mut item = match DelegatorSignStoreItem::find(task_id)? {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    if item.is_terminal() {
+        info!(
+            "sign task {} has finished with state {:?}, just ignore",
+            task_id, &item.state
+        );
+        return Ok(Some(Vec::new()));
+    }
+    if item.state == StoreItemState::Broadcasting {
+        info!(
+            "signed transaction of task {} is being broadcasted, can not cancel",
+            task_id
+        );
+        return Ok(Some(Vec::new()));
+    }
+    item.transition(StoreItemEvent::Cancelled, None)?;
+    DelegatorSignStoreItem::save(&item)?;
+    Ok(Some(item.participant_peer_ids()))
+}
+
+fn close_p2p_connections(task_id: &str) -> HandlerResult<()> {
+    let item = DelegatorSignStoreItem::get(task_id)?;
+    for peer_id in item.pinner_peer_ids() {
+        close_p2p(&peer_id)?;
+    }
+    if let Some(executor) = item.executor {
+        close_p2p(&executor.peer_id)?;
+    }
     Ok(())
 }
 
@@ -202,6 +444,15 @@ pub fn process_pinner_key_slice_response(
 ) -> anyhow::Result<()> {
     let deployment_id: String = res.deployment_id.clone();
     let mut item = DelegatorSignStoreItem::get(&res.task_id)?;
+    if item.state == StoreItemState::Cancelled {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &res.task_id,
+            "task has been cancelled".into(),
+            P2pReplyType::Rejected,
+        );
+    }
     if item.has_found_key_slice(&deployment_id) {
         return response_ipfs_p2p(
             reply_to,
@@ -212,11 +463,26 @@ pub fn process_pinner_key_slice_response(
         );
     }
 
+    let nonce_commitment = res.nonce_commitment.as_ref().map(|v| NonceCommitment {
+        index: v.index,
+        commitment: v.commitment.clone(),
+    });
+    if item.task_info.exec_info.sign_mode.is_threshold() && nonce_commitment.is_none() {
+        return response_ipfs_p2p(
+            reply_to,
+            peer_id,
+            &res.task_id,
+            &format!("pinner of {} returned no nonce commitment", &deployment_id),
+            P2pReplyType::Rejected,
+        );
+    }
+
     item.insert_key_slice_info(
         &deployment_id,
         KeySliceInfo {
             peer_id: peer_id.to_string(),
             encrypted_key_slice: res.encrypted_key_slice.clone(),
+            nonce_commitment,
         },
     )?;
     DelegatorSignStoreItem::save(&item)?;
@@ -226,7 +492,8 @@ pub fn process_pinner_key_slice_response(
 }
 
 fn try_send_to_executor(item: &mut DelegatorSignStoreItem) -> anyhow::Result<()> {
-    if !item.ready_send_to_executor() {
+    // key slices of spare deployments may arrive after the request has been sent
+    if item.state != StoreItemState::FindingDeployments || !item.ready_send_to_executor() {
         return Ok(());
     }
 
@@ -234,6 +501,8 @@ fn try_send_to_executor(item: &mut DelegatorSignStoreItem) -> anyhow::Result<()>
         "ready to send sign task to executor, task id: {}",
         &item.task_info.task_id
     );
+    let public_keys = item.public_keys()?;
+    item.transition(StoreItemEvent::ExecutionRequested, None)?;
     let encrypted_key_slices = item.get_encrypted_key_slices();
     let res = crate::p2p_proto::GeneralMsg {
         msg: Some(
@@ -244,11 +513,13 @@ fn try_send_to_executor(item: &mut DelegatorSignStoreItem) -> anyhow::Result<()>
                     p1_signature: item.p1_signature.clone(),
                     key_type: item.task_info.exec_info.task_type.clone(),
                     encrypted_key_slices,
+                    public_keys,
+                    signing_commitments: item.get_signing_commitments(),
                 },
             ),
         ),
     };
-    send_message(
+    send_signed_message(
         &item
             .executor
             .as_ref()
@@ -262,7 +533,5 @@ fn try_send_to_executor(item: &mut DelegatorSignStoreItem) -> anyhow::Result<()>
         &item.task_info.task_id,
         res,
     )?;
-
-    item.state = StoreItemState::SentToExecutor;
     DelegatorSignStoreItem::save(&item)
 }
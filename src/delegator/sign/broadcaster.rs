@@ -0,0 +1,70 @@
+mod bitcoin;
+#[cfg(test)]
+mod mock;
+
+use bitcoin::BitcoinBroadcaster;
+
+pub trait ChainBroadcaster {
+    /// Send the signed transaction to the network, returns the transaction id.
+    fn broadcast(&self, transaction_data: &[u8], witness: &[u8]) -> anyhow::Result<String>;
+}
+
+pub fn broadcaster_for(task_type: &str) -> anyhow::Result<Box<dyn ChainBroadcaster>> {
+    match task_type {
+        "bitcoin_mainnet" | "bitcoin_testnet" => Ok(Box::new(BitcoinBroadcaster::new(task_type))),
+        _ => Err(anyhow::anyhow!(
+            "{}:{} no broadcaster for task type {}",
+            line!(),
+            file!(),
+            task_type
+        )),
+    }
+}
+
+pub fn broadcast_signed_transaction(
+    broadcaster: &dyn ChainBroadcaster,
+    transaction_data: &[u8],
+    witness: &[u8],
+) -> anyhow::Result<String> {
+    if witness.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{}:{} can not broadcast transaction without witness",
+            line!(),
+            file!()
+        ));
+    }
+    let transaction_id = broadcaster.broadcast(transaction_data, witness)?;
+    info!(
+        "broadcast signed transaction {} successfully",
+        &transaction_id
+    );
+    Ok(transaction_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockBroadcaster;
+    use super::*;
+
+    #[test]
+    fn broadcaster_keyed_by_task_type() {
+        assert!(broadcaster_for("bitcoin_mainnet").is_ok());
+        assert!(broadcaster_for("bitcoin_testnet").is_ok());
+        assert!(broadcaster_for("unknown").is_err());
+    }
+
+    #[test]
+    fn broadcast_signed_transaction_works() -> anyhow::Result<()> {
+        let broadcaster = MockBroadcaster::default();
+        let transaction_id = broadcast_signed_transaction(&broadcaster, b"tx", b"witness")?;
+        assert_eq!("mock-0", transaction_id);
+        assert_eq!(
+            vec![(b"tx".to_vec(), b"witness".to_vec())],
+            broadcaster.sent()
+        );
+
+        assert!(broadcast_signed_transaction(&broadcaster, b"tx", &[]).is_err());
+        assert_eq!(1, broadcaster.sent().len());
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use super::ChainBroadcaster;
+use wascc_actor::untyped;
+
+const CAPABILITY_BITCOIN: &'static str = "tea:bitcoin";
+const OP_SEND_TRANSACTION: &'static str = "SendTransaction";
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SendTransactionRequest {
+    network: String,
+    transaction_data: Vec<u8>,
+    witness: Vec<u8>,
+}
+
+/// Sends transactions through the `tea:bitcoin` provider, which talks to an Electrum or
+/// bitcoind RPC endpoint of the given network.
+pub struct BitcoinBroadcaster {
+    network: String,
+}
+
+impl BitcoinBroadcaster {
+    pub fn new(task_type: &str) -> Self {
+        BitcoinBroadcaster {
+            network: task_type.trim_start_matches("bitcoin_").to_string(),
+        }
+    }
+}
+
+impl ChainBroadcaster for BitcoinBroadcaster {
+    fn broadcast(&self, transaction_data: &[u8], witness: &[u8]) -> anyhow::Result<String> {
+        let req = SendTransactionRequest {
+            network: self.network.clone(),
+            transaction_data: transaction_data.to_vec(),
+            witness: witness.to_vec(),
+        };
+        let res = untyped::default()
+            .call(
+                CAPABILITY_BITCOIN,
+                OP_SEND_TRANSACTION,
+                tea_codec::serialize(req)?,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let transaction_id: String = tea_codec::deserialize(res.as_slice())?;
+        Ok(transaction_id)
+    }
+}
@@ -0,0 +1,22 @@
+use super::ChainBroadcaster;
+use std::cell::RefCell;
+
+/// Records transactions locally instead of sending them to a network.
+#[derive(Default)]
+pub struct MockBroadcaster {
+    sent: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl MockBroadcaster {
+    pub fn sent(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.sent.borrow().clone()
+    }
+}
+
+impl ChainBroadcaster for MockBroadcaster {
+    fn broadcast(&self, transaction_data: &[u8], witness: &[u8]) -> anyhow::Result<String> {
+        let mut sent = self.sent.borrow_mut();
+        sent.push((transaction_data.to_vec(), witness.to_vec()));
+        Ok(format!("mock-{}", sent.len() - 1))
+    }
+}
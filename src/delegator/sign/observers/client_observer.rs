@@ -1,3 +1,4 @@
+use crate::common::send_signed_message;
 use crate::delegator::{
     executor_info::ExecutorInfo,
     sign::{
@@ -8,14 +9,13 @@ use crate::delegator::{
         try_send_to_executor,
     },
 };
-use tea_actor_utility::ipfs_p2p::send_message;
 
 // this property value set in pinner actor in response_peer_approve_pinner_handler method
 const PROPERTY_KEY_DEPLOYMENT_ID: &'static str = "deployment_id";
 
 pub fn operation_after_verify_handler(
     peer_id: String,
-    _ephemeral_id: Vec<u8>,
+    ephemeral_id: Vec<u8>,
     item: &crate::actor_pinner_proto::ChallangeStoreItem,
 ) -> anyhow::Result<()> {
     let task_id = &item
@@ -45,7 +45,7 @@ pub fn operation_after_verify_handler(
                 .clone(),
         )?;
 
-        on_executor_ra_success(task_id, &peer_id, rsa_pub_key)?;
+        on_executor_ra_success(task_id, &peer_id, ephemeral_id, rsa_pub_key)?;
     } else if is_pinner_ra_response(item) {
         let deployment_id = &item
             .properties
@@ -66,19 +66,29 @@ pub fn operation_after_verify_handler(
 fn on_executor_ra_success(
     task_id: &str,
     peer_id: &str,
+    ephemeral_id: Vec<u8>,
     rsa_pub_key: Vec<u8>,
 ) -> anyhow::Result<()> {
     debug!("got sign executor ra success response, task id: {}, peer id: {}", task_id, peer_id);
 
     let mut store_item = DelegatorSignStoreItem::get(task_id)?;
+    if store_item.is_terminal() {
+        info!("sign task {} has finished, just ignore", task_id);
+        return Ok(());
+    }
     if store_item.executor.is_some() {
         info!("executor already exists, just ignore");
         return Ok(());
     }
+    if store_item.is_blacklisted_executor(peer_id) {
+        info!("executor {} has been blacklisted, just ignore", peer_id);
+        return Ok(());
+    }
 
     store_item.executor = Some(ExecutorInfo {
         peer_id: peer_id.to_string(),
         rsa_pub_key,
+        ephemeral_id,
     });
     let candidates = store_item.pop_all_candidates();
     DelegatorSignStoreItem::save(&store_item)?;
@@ -99,6 +109,10 @@ fn on_pinner_ra_success(task_id: &str, peer_id: &str, deployment_id: &str) -> an
     debug!("go sign pinner ra success response, task id: {}, peer id: {}", task_id, peer_id);
 
     let mut store_item = DelegatorSignStoreItem::get(task_id)?;
+    if store_item.is_terminal() {
+        info!("sign task {} has finished, just ignore", task_id);
+        return Ok(());
+    }
     if store_item.has_found_key_slice(deployment_id) {
         info!(
             "key_slice with deployment_id {} already exists, just ignore",
@@ -131,10 +145,18 @@ fn send_get_pinner_key_slice_request(
                 crate::p2p_proto::TaskSignGetPinnerKeySliceRequest {
                     task_id: task_id.to_string(),
                     rsa_pub_key: item.executor.as_ref().unwrap().rsa_pub_key.clone(),
+                    executor_peer_id: item.executor.as_ref().unwrap().peer_id.clone(),
                     deployment_id: deployment_id.to_string(),
+                    nonce_commit: if item.task_info.exec_info.sign_mode.is_threshold() {
+                        Some(crate::p2p_proto::NonceCommitRequest {
+                            adhoc_data: item.transaction_data.clone(),
+                        })
+                    } else {
+                        None
+                    },
                 },
             ),
         ),
     };
-    send_message(peer_id, task_id, req)
+    send_signed_message(peer_id, task_id, req)
 }
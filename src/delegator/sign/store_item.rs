@@ -1,13 +1,19 @@
-use crate::common::{ExecutionInfo, TaskInfo};
+use crate::common::retention::{self, TaskKind};
+use crate::common::state_machine::{self, StateMachine, Transition};
+use crate::common::utils::current_timestamp;
+use crate::common::{ExecutionInfo, TaskInfo, TaskPayment};
 use crate::delegator::executor_info::ExecutorInfo;
 use crate::BINDING_NAME;
 use std::collections::HashMap;
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
+use tea_actor_utility::actor_crypto;
 use tea_actor_utility::actor_kvp;
 use tea_actor_utility::actor_kvp::ShabbyLock;
 use tea_codec::error::TeaError;
 
 const PREFIX_DELEGATOR_TASK_SIGN_STORE_ITEM: &'static str = "delegator_task_sign_store_item";
+/// Executors elected again after invalid witnesses, the task is rejected beyond that
+const MAX_EXECUTOR_REELECTIONS: usize = 3;
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum StoreItemState {
@@ -15,7 +21,65 @@ pub enum StoreItemState {
     Initialized,
     FindingDeployments,
     SentToExecutor,
+    /// Witness is accepted and kept, the transaction is being broadcasted and reported to layer1
+    Broadcasting,
     CommitResult,
+    Rejected,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum StoreItemEvent {
+    /// Asset info is loaded and p1 signature is valid
+    Initialized,
+    /// Invalid sign request, or too many executors committed invalid witnesses
+    Rejected,
+    ExecutorsInvited,
+    ExecutionRequested,
+    /// Executor committed an invalid witness, another executor will be chosen
+    WitnessRejected,
+    WitnessAccepted,
+    /// Layer1 has accepted the sign result
+    ResultCommitted,
+    Cancelled,
+}
+
+impl StateMachine for StoreItemState {
+    type Event = StoreItemEvent;
+
+    fn next(&self, event: &StoreItemEvent) -> Option<Self> {
+        match (self, event) {
+            (StoreItemState::Init, StoreItemEvent::Initialized) => {
+                Some(StoreItemState::Initialized)
+            }
+            (StoreItemState::Init, StoreItemEvent::Rejected)
+            | (StoreItemState::SentToExecutor, StoreItemEvent::Rejected) => {
+                Some(StoreItemState::Rejected)
+            }
+            (StoreItemState::Initialized, StoreItemEvent::ExecutorsInvited) => {
+                Some(StoreItemState::FindingDeployments)
+            }
+            (StoreItemState::FindingDeployments, StoreItemEvent::ExecutionRequested) => {
+                Some(StoreItemState::SentToExecutor)
+            }
+            (StoreItemState::SentToExecutor, StoreItemEvent::WitnessRejected) => {
+                Some(StoreItemState::FindingDeployments)
+            }
+            (StoreItemState::SentToExecutor, StoreItemEvent::WitnessAccepted) => {
+                Some(StoreItemState::Broadcasting)
+            }
+            (StoreItemState::Broadcasting, StoreItemEvent::ResultCommitted) => {
+                Some(StoreItemState::CommitResult)
+            }
+            (StoreItemState::Init, StoreItemEvent::Cancelled)
+            | (StoreItemState::Initialized, StoreItemEvent::Cancelled)
+            | (StoreItemState::FindingDeployments, StoreItemEvent::Cancelled)
+            | (StoreItemState::SentToExecutor, StoreItemEvent::Cancelled) => {
+                Some(StoreItemState::Cancelled)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -23,6 +87,17 @@ pub enum StoreItemState {
 pub struct KeySliceInfo {
     pub peer_id: String,
     pub encrypted_key_slice: Vec<u8>,
+    #[serde(default)]
+    pub nonce_commitment: Option<NonceCommitment>,
+}
+
+/// Nonce commitment a pinner made with its key slice in the first round of threshold sign, the
+/// executor asks the pinner for the partial signature with it in the second round.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonceCommitment {
+    pub index: u32,
+    pub commitment: Vec<u8>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -32,9 +107,23 @@ pub struct DelegatorSignStoreItem {
     pub state: StoreItemState,
     pub executor: Option<ExecutorInfo>,
     pub multi_sig_account: Vec<u8>,
+    #[serde(default)]
+    pub p1_public_key: Vec<u8>,
+    #[serde(default)]
+    pub p2_public_key: Vec<u8>,
+    #[serde(default)]
+    pub p3_public_key: Option<Vec<u8>>,
     pub p1_signature: Vec<u8>,
     pub transaction_data: Vec<u8>,
     pub nonce: Vec<u8>,
+    #[serde(default)]
+    pub witness: Option<Vec<u8>>,
+    #[serde(default)]
+    pub transaction_id: Option<String>,
+    #[serde(default)]
+    pub blacklisted_executors: Vec<String>,
+    #[serde(default)]
+    pub transitions: Vec<Transition<StoreItemState, StoreItemEvent>>,
     key_slices: HashMap<String, Option<KeySliceInfo>>,
     deployment_candidates: HashMap<String, Vec<String>>,
 }
@@ -48,31 +137,60 @@ impl TryFrom<crate::actor_delegate_proto::SignTransactionResponse> for Delegator
         Ok(DelegatorSignStoreItem {
             task_info: TaskInfo {
                 task_id: base64::encode(&value.task_id),
-                exec_info: ExecutionInfo {
-                    n: 0,
-                    k: 0,
-                    task_type: "".to_string(),
-                },
+                exec_info: ExecutionInfo::default(),
+                payment: TaskPayment::from(&value.payment),
             },
             nonce: Vec::new(),
+            witness: None,
+            transaction_id: None,
             state: StoreItemState::Init,
             executor: None,
             multi_sig_account: value.multi_sig_account,
+            p1_public_key: Vec::new(),
+            p2_public_key: Vec::new(),
+            p3_public_key: None,
             p1_signature: value.p1_signature,
             transaction_data: value.data_adhoc.transaction_data,
+            blacklisted_executors: Vec::new(),
+            transitions: Vec::new(),
             key_slices: HashMap::new(),
             deployment_candidates: HashMap::new(),
         })
     }
 }
 
+impl TryInto<crate::actor_delegate_proto::UpdateSignResult> for DelegatorSignStoreItem {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<crate::actor_delegate_proto::UpdateSignResult, Self::Error> {
+        let executor = self.executor.ok_or(anyhow::anyhow!("executor is empty"))?;
+        let witness = self.witness.ok_or(anyhow::anyhow!("witness is empty"))?;
+        Ok(crate::actor_delegate_proto::UpdateSignResult {
+            task_id: base64::decode(self.task_info.task_id)?,
+            delegator_nonce: self.nonce,
+            witness_hash: actor_crypto::sha256(witness)?,
+            executor_peer_id: executor.peer_id,
+            executor_ephemeral_id: executor.ephemeral_id,
+        })
+    }
+}
+
 impl DelegatorSignStoreItem {
     pub fn get(task_id: &str) -> anyhow::Result<Self> {
-        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
-        actor_kvp::get::<DelegatorSignStoreItem>(BINDING_NAME, &get_task_store_item_key(task_id))?
+        DelegatorSignStoreItem::find(task_id)?
             .ok_or(TeaError::CommonError(format!("can not find task {}", task_id)).into())
     }
 
+    /// `None` if I'm not delegator of the sign task, which is how a task cancelled by layer1 is
+    /// told apart from a key generation one.
+    pub fn find(task_id: &str) -> anyhow::Result<Option<Self>> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(actor_kvp::get::<DelegatorSignStoreItem>(
+            BINDING_NAME,
+            &get_task_store_item_key(task_id),
+        )?)
+    }
+
     pub fn save(item: &DelegatorSignStoreItem) -> anyhow::Result<()> {
         let _lock = ShabbyLock::lock(BINDING_NAME, &item.task_info.task_id);
         actor_kvp::set_forever(
@@ -80,9 +198,141 @@ impl DelegatorSignStoreItem {
             &get_task_store_item_key(&item.task_info.task_id),
             item,
         )?;
+        retention::touch(TaskKind::DelegatorSign, &item.task_info.task_id)?;
+        if item.is_terminal() {
+            retention::mark_finished(
+                TaskKind::DelegatorSign,
+                &item.task_info.task_id,
+                &item.state,
+            )?;
+        }
         Ok(())
     }
 
+    pub fn delete(task_id: &str) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        actor_kvp::del(BINDING_NAME, &get_task_store_item_key(task_id))?;
+        Ok(())
+    }
+
+    /// Checks P1 signature of the transaction data with the key type of the asset, through the
+    /// `actor_crypto::verify` host call.
+    pub fn verify_p1_signature(&self) -> anyhow::Result<bool> {
+        if self.p1_public_key.is_empty() || self.p1_signature.is_empty() {
+            return Ok(false);
+        }
+        actor_crypto::verify(
+            self.task_info.exec_info.task_type.clone(),
+            self.p1_public_key.clone(),
+            self.transaction_data.clone(),
+            self.p1_signature.clone(),
+        )
+    }
+
+    /// Public keys of the multi-sig account, ordered as P1, P2 and optional P3. The executor can
+    /// not build the witness without P1 and P2, an asset missing either of them is an error.
+    pub fn public_keys(&self) -> anyhow::Result<Vec<Vec<u8>>> {
+        if self.p1_public_key.is_empty() || self.p2_public_key.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{}:{} p1 or p2 public key of task {} is empty",
+                line!(),
+                file!(),
+                &self.task_info.task_id
+            ));
+        }
+        let mut keys = vec![self.p1_public_key.clone(), self.p2_public_key.clone()];
+        if let Some(p3) = self.p3_public_key.as_ref() {
+            keys.push(p3.clone());
+        }
+        Ok(keys)
+    }
+
+    /// Checks the witness against the multi-sig account through the `actor_crypto::verify_witness`
+    /// host call.
+    pub fn verify_witness(&self, witness: &[u8]) -> anyhow::Result<bool> {
+        actor_crypto::verify_witness(
+            self.task_info.exec_info.task_type.clone(),
+            self.multi_sig_account.clone(),
+            self.transaction_data.clone(),
+            witness.to_vec(),
+        )
+    }
+
+    pub fn is_executor(&self, peer_id: &str) -> bool {
+        match self.executor.as_ref() {
+            Some(executor) => executor.peer_id == peer_id,
+            None => false,
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        match self.state {
+            StoreItemState::CommitResult | StoreItemState::Rejected | StoreItemState::Cancelled => {
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the sign task by `event`, `peer_id` is the executor whose commit caused it if any.
+    /// Layer1 and local events have no peer.
+    pub fn transition(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.transition_at(event, peer_id, current_timestamp()?)
+    }
+
+    pub fn transition_at(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        state_machine::transit(
+            &self.task_info.task_id,
+            &mut self.state,
+            &mut self.transitions,
+            event,
+            peer_id,
+            timestamp,
+        )
+    }
+
+    /// Peers that may hold store items or RSA keys of the task, blacklisted executors included.
+    pub fn participant_peer_ids(&self) -> Vec<String> {
+        let mut peer_ids = self.pinner_peer_ids();
+        peer_ids.extend(self.executor.iter().map(|v| v.peer_id.clone()));
+        peer_ids.extend(self.blacklisted_executors.iter().cloned());
+        peer_ids
+    }
+
+    /// Whether another executor may be elected after current executor is blacklisted.
+    pub fn can_reelect_executor(&self) -> bool {
+        self.blacklisted_executors.len() <= MAX_EXECUTOR_REELECTIONS
+    }
+
+    pub fn is_blacklisted_executor(&self, peer_id: &str) -> bool {
+        self.blacklisted_executors.iter().any(|v| v == peer_id)
+    }
+
+    /// Blacklist current executor and release the key slices encrypted for it, pinners of
+    /// those key slices become deployment candidates again for the next executor.
+    pub fn blacklist_executor(&mut self) {
+        if let Some(executor) = self.executor.take() {
+            self.blacklisted_executors.push(executor.peer_id);
+        }
+        for (deployment_id, slice) in self.key_slices.iter_mut() {
+            if let Some(info) = slice.take() {
+                self.deployment_candidates
+                    .entry(deployment_id.clone())
+                    .or_insert_with(Vec::new)
+                    .push(info.peer_id);
+            }
+        }
+    }
+
     pub fn init_deployment_resources(&mut self, deployment_ids: &Vec<String>) {
         for id in deployment_ids {
             self.key_slices.insert(id.clone(), None);
@@ -140,6 +390,13 @@ impl DelegatorSignStoreItem {
         self.deployment_candidates.drain().collect()
     }
 
+    pub fn pinner_peer_ids(&self) -> Vec<String> {
+        self.key_slices
+            .values()
+            .filter_map(|v| v.as_ref().map(|info| info.peer_id.clone()))
+            .collect()
+    }
+
     pub fn get_encrypted_key_slices(&self) -> Vec<Vec<u8>> {
         let mut rtn: Vec<Vec<u8>> = Vec::new();
         for (_, v) in self.key_slices.iter() {
@@ -149,8 +406,129 @@ impl DelegatorSignStoreItem {
         }
         rtn
     }
+
+    /// Nonce commitments of pinners ordered by share index, with the pinner to ask for the
+    /// partial signature of each.
+    pub fn get_signing_commitments(&self) -> Vec<crate::p2p_proto::SigningCommitment> {
+        let mut commitments: Vec<crate::p2p_proto::SigningCommitment> = self
+            .key_slices
+            .iter()
+            .filter_map(|(deployment_id, v)| {
+                let info = v.as_ref()?;
+                let nonce_commitment = info.nonce_commitment.as_ref()?;
+                Some(crate::p2p_proto::SigningCommitment {
+                    peer_id: info.peer_id.clone(),
+                    deployment_id: deployment_id.clone(),
+                    index: nonce_commitment.index,
+                    commitment: nonce_commitment.commitment.clone(),
+                })
+            })
+            .collect();
+        commitments.sort_by_key(|v| v.index);
+        commitments
+    }
 }
 
 fn get_task_store_item_key(task_id: &str) -> String {
     format!("{}_{}", PREFIX_DELEGATOR_TASK_SIGN_STORE_ITEM, task_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::state_machine::check_transition_table;
+
+    fn new_item() -> DelegatorSignStoreItem {
+        DelegatorSignStoreItem {
+            task_info: TaskInfo {
+                task_id: "task".into(),
+                exec_info: ExecutionInfo::default(),
+                payment: TaskPayment::default(),
+            },
+            state: StoreItemState::Init,
+            executor: None,
+            multi_sig_account: Vec::new(),
+            p1_public_key: Vec::new(),
+            p2_public_key: Vec::new(),
+            p3_public_key: None,
+            p1_signature: Vec::new(),
+            transaction_data: Vec::new(),
+            nonce: Vec::new(),
+            witness: None,
+            transaction_id: None,
+            blacklisted_executors: Vec::new(),
+            transitions: Vec::new(),
+            key_slices: HashMap::new(),
+            deployment_candidates: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        use super::StoreItemEvent as E;
+        use super::StoreItemState as S;
+        check_transition_table(
+            &[
+                S::Init,
+                S::Initialized,
+                S::FindingDeployments,
+                S::SentToExecutor,
+                S::Broadcasting,
+                S::CommitResult,
+                S::Rejected,
+                S::Cancelled,
+            ],
+            &[
+                E::Initialized,
+                E::Rejected,
+                E::ExecutorsInvited,
+                E::ExecutionRequested,
+                E::WitnessRejected,
+                E::WitnessAccepted,
+                E::ResultCommitted,
+                E::Cancelled,
+            ],
+            &[
+                (S::Init, E::Initialized, S::Initialized),
+                (S::Init, E::Rejected, S::Rejected),
+                (S::Initialized, E::ExecutorsInvited, S::FindingDeployments),
+                (
+                    S::FindingDeployments,
+                    E::ExecutionRequested,
+                    S::SentToExecutor,
+                ),
+                // another executor is elected after an invalid witness
+                (S::SentToExecutor, E::WitnessRejected, S::FindingDeployments),
+                (S::SentToExecutor, E::Rejected, S::Rejected),
+                (S::SentToExecutor, E::WitnessAccepted, S::Broadcasting),
+                (S::Broadcasting, E::ResultCommitted, S::CommitResult),
+                (S::Init, E::Cancelled, S::Cancelled),
+                (S::Initialized, E::Cancelled, S::Cancelled),
+                (S::FindingDeployments, E::Cancelled, S::Cancelled),
+                (S::SentToExecutor, E::Cancelled, S::Cancelled),
+            ],
+        );
+    }
+
+    #[test]
+    fn executors_are_reelected_a_limited_number_of_times() {
+        let mut item = new_item();
+        for i in 0..MAX_EXECUTOR_REELECTIONS {
+            item.blacklisted_executors.push(format!("e{}", i));
+            assert!(item.can_reelect_executor());
+        }
+        item.blacklisted_executors.push("last".into());
+        assert!(!item.can_reelect_executor());
+    }
+
+    #[test]
+    fn public_keys_require_p1_and_p2() {
+        let mut item = new_item();
+        item.p1_public_key = vec![1];
+        assert!(item.public_keys().is_err());
+        item.p2_public_key = vec![2];
+        assert_eq!(vec![vec![1], vec![2]], item.public_keys().unwrap());
+        item.p3_public_key = Some(vec![3]);
+        assert_eq!(3, item.public_keys().unwrap().len());
+    }
+}
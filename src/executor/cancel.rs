@@ -0,0 +1,28 @@
+use crate::common::{rsa_key::wipe_rsa_private_key, wipe_key_generation_rsa_key, TaskRoleRecord};
+use crate::executor::sign::PREFIX_SIGN_RSA_KEY;
+use crate::executor::store_item::{ExecutorStoreItem, StoreItemEvent};
+
+/// Cancels my executor task and wipes its RSA key, `delegator_peer_id` is checked if the
+/// cancellation comes through p2p instead of layer1.
+pub fn cancel_task(task_id: &str, delegator_peer_id: Option<&str>) -> anyhow::Result<()> {
+    let mut item = match ExecutorStoreItem::find(task_id)? {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    if let Some(peer_id) = delegator_peer_id {
+        item.check_delegator(peer_id)?;
+    }
+    if item.is_terminal() {
+        return Ok(());
+    }
+
+    info!("cancel executor task {}", task_id);
+    item.transition(StoreItemEvent::Cancelled, delegator_peer_id)?;
+    ExecutorStoreItem::save(&item)?;
+    // key generation tasks have a role record, sign tasks do not
+    if TaskRoleRecord::contains(task_id)? {
+        wipe_key_generation_rsa_key(task_id)
+    } else {
+        wipe_rsa_private_key(PREFIX_SIGN_RSA_KEY, task_id)
+    }
+}
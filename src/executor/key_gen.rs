@@ -1,14 +1,29 @@
-use crate::common::{send_key_generation_request, verify_to_candidate_signature};
-use crate::executor::store_item::{ExecutorStoreItem, StoreItemState};
+use crate::common::{
+    decrypt_key_slice,
+    error::TaskError,
+    hybrid, idempotency,
+    payment::willing_to_run,
+    retention::TaskKind,
+    secret::{expose_all, SecretBuffer},
+    send_key_generation_request, send_signed_message,
+    utils::my_peer_id,
+    verify_to_candidate_signature, vss, wipe_key_generation_rsa_key, TaskRole, TaskRoleRecord,
+};
+use crate::executor::store_item::{ExecutorStoreItem, StoreItemEvent};
 use serde::export::TryFrom;
 use tea_actor_utility::{
     actor_crypto,
-    actor_crypto::generate_multi_sig_asset,
+    actor_crypto::{generate_aes_key, generate_multi_sig_asset},
     actor_nats::response_reply_with_subject,
     actor_util::rsa_encrypt,
-    ipfs_p2p::{log_and_response_with_error, send_message},
+    encode_protobuf,
+    ipfs_p2p::log_and_response_with_error,
 };
 
+/// P1 and P2 sign normally, P3 (if any) is the user's cold key to recover funds together with P1
+/// when the TEA network is not available.
+pub const MULTI_SIG_THRESHOLD: u8 = 2;
+
 pub fn task_key_generation_candidate_request_handler(
     peer_id: String,
     req: crate::p2p_proto::KeyGenerationCandidateRequest,
@@ -16,8 +31,9 @@ pub fn task_key_generation_candidate_request_handler(
     trace!("executor received KeyGenerationCandidateRequest: {:?}", req);
     verify_to_candidate_signature(&peer_id.clone(), &req.clone(), move || {
         let mut store_item = ExecutorStoreItem::try_from(req.clone())?;
+        store_item.delegator_peer_id = peer_id.clone();
 
-        if !willing_to_run(&store_item) {
+        if !willing_to_run(&store_item.task_info, &TaskRole::agreed_roles(true))? {
             info!(
                 "I'm not willing to run {}, just ignore",
                 &store_item.task_info.task_id
@@ -32,20 +48,17 @@ pub fn task_key_generation_candidate_request_handler(
             );
             return Ok(());
         }
+        let record = TaskRoleRecord::new(store_item.task_info.clone(), true);
+        TaskRoleRecord::save(&record)?;
         ExecutorStoreItem::save(&store_item)?;
 
-        send_key_generation_request(&peer_id, &store_item.task_info, true)?;
-        store_item.state = StoreItemState::Requested;
+        send_key_generation_request(&peer_id, &record)?;
+        store_item.transition(StoreItemEvent::Applied, Some(peer_id.as_str()))?;
         ExecutorStoreItem::save(&store_item)?;
         Ok(())
     })
 }
 
-fn willing_to_run(_item: &ExecutorStoreItem) -> bool {
-    // TODO check errand payment plan and i'm willing to run it
-    true
-}
-
 fn check_capabilities(_item: &ExecutorStoreItem) -> anyhow::Result<()> {
     // todo check if item.task_info.code_cid has deployed
 
@@ -58,13 +71,35 @@ pub fn task_execution_request_handler(
     peer_id: &str,
     reply_to: &str,
 ) -> anyhow::Result<()> {
-    match ExecutorStoreItem::get(&request.task_id) {
+    let key = idempotency::message_key(
+        "TaskExecutionRequest",
+        peer_id,
+        &encode_protobuf(request.clone())?,
+    );
+    if !idempotency::claim(TaskKind::Executor, &request.task_id, &key)? {
+        return idempotency::acknowledge_duplicate(reply_to, &request.task_id);
+    }
+    let task_id = request.task_id.clone();
+    let result = execute_task(request, peer_id, reply_to, &key);
+    idempotency::release_on_error(TaskKind::Executor, &task_id, &key, result)
+}
+
+fn execute_task(
+    request: crate::p2p_proto::TaskExecutionRequest,
+    peer_id: &str,
+    reply_to: &str,
+    key: &str,
+) -> anyhow::Result<()> {
+    match take_executor_role(&request.task_id, peer_id) {
         Ok(mut item) => {
-            item.state = StoreItemState::Responded;
+            if item.is_terminal() {
+                return Err(TaskError::TaskExpired(item.task_info.task_id).into());
+            }
+            item.transition(StoreItemEvent::InputReceived, Some(peer_id))?;
             ExecutorStoreItem::save(&item)?;
 
             let res = generate_task_execution_response(&item, &request)?;
-            send_message(
+            send_signed_message(
                 peer_id,
                 &item.task_info.task_id,
                 crate::p2p_proto::GeneralMsg {
@@ -73,8 +108,10 @@ pub fn task_execution_request_handler(
                     )),
                 },
             )?;
-            item.state = StoreItemState::Executed;
+            item.transition(StoreItemEvent::Executed, Some(peer_id))?;
             ExecutorStoreItem::save(&item)?;
+            idempotency::mark_processed(TaskKind::Executor, &request.task_id, key)?;
+            wipe_key_generation_rsa_key(&item.task_info.task_id)?;
 
             response_reply_with_subject(
                 "",
@@ -96,6 +133,14 @@ pub fn task_execution_request_handler(
     }
 }
 
+/// The executor role is taken only if the request comes from the delegator who invited me.
+fn take_executor_role(task_id: &str, delegator_peer_id: &str) -> anyhow::Result<ExecutorStoreItem> {
+    let item = ExecutorStoreItem::get(task_id)?;
+    item.check_delegator(delegator_peer_id)?;
+    TaskRoleRecord::take_role(task_id, TaskRole::Executor)?;
+    Ok(item)
+}
+
 pub fn generate_task_execution_response(
     item: &ExecutorStoreItem,
     request: &crate::p2p_proto::TaskExecutionRequest,
@@ -104,32 +149,56 @@ pub fn generate_task_execution_response(
         "generating task execution response got request {:?}",
         request
     );
-    let (pk, sk) = generate_key_by_type(&request.key_type)?;
-    let key_slices = actor_crypto::shamir_share(
+    let (pk, sk) = match request.reshare.as_ref() {
+        Some(reshare) => (None, recover_key_to_reshare(&request.task_id, reshare)?),
+        None => {
+            let (pk, sk) = generate_key_by_type(&request.key_type)?;
+            (Some(pk), sk)
+        }
+    };
+    let (key_slices, share_commitments) = vss::split_with_commitments(
+        &request.key_type,
         request.initial_pinners.len() as u8,
         request.minimum_recovery_number as u8,
-        sk,
+        &sk,
     )?;
+    drop(sk);
+    // the first commitment commits to the recovered private key, that is P2 public key
+    let pk = match pk {
+        Some(pk) => pk,
+        None => share_commitments.first().cloned().ok_or(anyhow::anyhow!(
+            "{}:{} share commitments are empty",
+            line!(),
+            file!()
+        ))?,
+    };
 
-    let mut index = 0;
     let mut initial_pinners: Vec<crate::p2p_proto::TaskResultInitialPinnerData> = Vec::new();
-    for pinner_data in request.initial_pinners.iter() {
+    for (index, pinner_data) in request.initial_pinners.iter().enumerate() {
+        let aes_key: SecretBuffer = generate_aes_key()?.into();
         initial_pinners.push(crate::p2p_proto::TaskResultInitialPinnerData {
             peer_id: pinner_data.peer_id.clone(),
-            encrypted_key_slice: rsa_encrypt(
-                pinner_data.rsa_pub_key.clone(),
-                key_slices[index].clone(),
+            encrypted_key_slice: hybrid::encrypt_key_slice(
+                &aes_key,
+                &request.task_id,
+                &pinner_data.peer_id,
+                vss::share_x_coordinate(index),
+                key_slices[index].expose(),
+                |key| Ok(rsa_encrypt(pinner_data.rsa_pub_key.clone(), key)?),
             )?,
         });
-        index += 1;
     }
+    drop(key_slices);
 
-    let p1 = request.p1_public_key.clone();
+    let p3 = if request.p3_public_key.is_empty() {
+        None
+    } else {
+        Some(request.p3_public_key.clone())
+    };
     let multi_sig_account = generate_multi_sig_account(
-        &p1,
+        &request.p1_public_key,
         &pk,
-        None,
-        item.task_info.exec_info.k,
+        p3,
         &item.task_info.exec_info.task_type,
     )?;
 
@@ -138,6 +207,7 @@ pub fn generate_task_execution_response(
         initial_pinners,
         p2_public_key: pk,
         multi_sig_account,
+        share_commitments,
     })
 }
 
@@ -145,14 +215,14 @@ fn generate_multi_sig_account(
     p1: &[u8],
     p2: &[u8],
     p3: Option<Vec<u8>>,
-    k: u8,
     task_type: &str,
 ) -> anyhow::Result<Vec<u8>> {
     let mut public_keys = vec![p1.to_vec(), p2.to_vec()];
     if let Some(p3) = p3 {
         public_keys.push(p3);
     }
-    let multi_sig_account = generate_multi_sig_asset(k, public_keys, task_type.to_string())?;
+    let multi_sig_account =
+        generate_multi_sig_asset(MULTI_SIG_THRESHOLD, public_keys, task_type.to_string())?;
     debug!(
         "executor generated multi sig account is: {}",
         &multi_sig_account
@@ -160,6 +230,25 @@ fn generate_multi_sig_account(
     Ok(multi_sig_account.into_bytes())
 }
 
-fn generate_key_by_type(key_type: &str) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
-    actor_crypto::generate(key_type.to_string())
+fn recover_key_to_reshare(
+    task_id: &str,
+    reshare: &crate::p2p_proto::ReshareKeySlices,
+) -> anyhow::Result<SecretBuffer> {
+    let key_slices = hybrid::decrypt_key_slices(
+        task_id,
+        &my_peer_id()?,
+        reshare.encrypted_key_slices.clone(),
+        |key| decrypt_key_slice(task_id, key),
+    )?;
+    let private_key: SecretBuffer = actor_crypto::shamir_recovery(
+        reshare.minimum_recovery_number as u8,
+        &expose_all(&key_slices),
+    )?
+    .into();
+    Ok(private_key)
+}
+
+fn generate_key_by_type(key_type: &str) -> anyhow::Result<(Vec<u8>, SecretBuffer)> {
+    let (pk, sk) = actor_crypto::generate(key_type.to_string())?;
+    Ok((pk, sk.into()))
 }
@@ -1,13 +1,21 @@
-use crate::executor::store_item::{ExecutorStoreItem, StoreItemState};
-use crate::BINDING_NAME;
+use crate::common::{
+    error::TaskError,
+    hybrid,
+    payment::willing_to_run,
+    rsa_key::{rsa_decrypt_by_task, save_rsa_private_key, wipe_rsa_private_key},
+    secret::{expose_all, SecretBuffer},
+    send_signed_message,
+    utils::my_peer_id,
+    SignMode, TaskRole,
+};
+use crate::executor::key_gen::MULTI_SIG_THRESHOLD;
+use crate::executor::store_item::{ExecutorStoreItem, StoreItemEvent, ThresholdSignInfo};
 use std::convert::TryFrom;
 use tea_actor_utility::{
     actor_crypto,
     actor_crypto::combine_to_witness,
-    actor_kvp,
     actor_nats::response_reply_with_subject,
-    actor_util::{generate_rsa_keypair, rsa_decrypt, rsa_key_to_bytes},
-    ipfs_p2p::send_message,
+    actor_util::{generate_rsa_keypair, rsa_key_to_bytes},
 };
 
 pub const PREFIX_SIGN_RSA_KEY: &'static str = "sign_rsa_key";
@@ -23,29 +31,135 @@ pub fn task_sign_with_key_slices_response_handler(
     );
     let task_id = request.task_id.clone();
     let mut item = ExecutorStoreItem::get(&task_id)?;
-    item.state = StoreItemState::Responded;
+    if item.is_terminal() {
+        return Err(TaskError::TaskExpired(task_id).into());
+    }
+    item.check_delegator(peer_id)?;
+    check_public_keys(&request.public_keys)?;
+    item.transition(StoreItemEvent::InputReceived, Some(peer_id))?;
     ExecutorStoreItem::save(&item)?;
 
-    let mut key_slices: Vec<Vec<u8>> = Vec::new();
-    for encrypted_key_slice in request.encrypted_key_slices {
-        key_slices.push(decrypt_key_slice(&task_id, encrypted_key_slice)?);
+    if item.task_info.exec_info.sign_mode == SignMode::Threshold {
+        return request_partial_signatures(item, &request, reply_to);
     }
 
-    let p2_private_key: Vec<u8> =
-        actor_crypto::shamir_recovery(item.task_info.exec_info.k, key_slices)?;
+    let p2_signature = recover_and_sign(&item, &request)?;
+    commit_sign_result(
+        item,
+        peer_id,
+        request.p1_signature,
+        request.public_keys,
+        p2_signature,
+    )?;
+    response_reply_with_subject("", reply_to, "signed successfully".as_bytes().to_vec())
+}
 
-    let p2_signature: Vec<u8> =
-        actor_crypto::sign(request.key_type, p2_private_key, request.adhoc_data)?;
+/// Second round of threshold sign, each chosen pinner is asked for its partial signature with
+/// the nonce commitments of all chosen pinners.
+fn request_partial_signatures(
+    mut item: ExecutorStoreItem,
+    request: &crate::p2p_proto::TaskSignWithKeySlicesResponse,
+    reply_to: &str,
+) -> anyhow::Result<()> {
+    let info = ThresholdSignInfo::new(request, item.task_info.exec_info.k)?;
+    let commitments = info.commitments();
+    item.threshold_sign = Some(info);
+    ExecutorStoreItem::save(&item)?;
+
+    for commitment in commitments.iter() {
+        send_signed_message(
+            &commitment.peer_id,
+            &item.task_info.task_id,
+            crate::p2p_proto::GeneralMsg {
+                msg: Some(crate::p2p_proto::general_msg::Msg::TaskPartialSignRequest(
+                    crate::p2p_proto::TaskPartialSignRequest {
+                        task_id: item.task_info.task_id.clone(),
+                        deployment_id: commitment.deployment_id.clone(),
+                        commitments: commitments.clone(),
+                    },
+                )),
+            },
+        )?;
+    }
+    response_reply_with_subject(
+        "",
+        reply_to,
+        "partial signatures requested".as_bytes().to_vec(),
+    )
+}
+
+pub fn task_partial_sign_response_handler(
+    res: crate::p2p_proto::TaskPartialSignResponse,
+    peer_id: &str,
+    reply_to: &str,
+) -> anyhow::Result<()> {
+    let mut item = ExecutorStoreItem::get(&res.task_id)?;
+    if item.is_terminal() {
+        return Err(TaskError::TaskExpired(res.task_id).into());
+    }
+    let partial_signature = res.partial_signature.ok_or(anyhow::anyhow!(
+        "{}:{} partial signature of task {} is empty",
+        line!(),
+        file!(),
+        &res.task_id
+    ))?;
+    let info = item.threshold_sign.as_mut().ok_or(anyhow::anyhow!(
+        "{}:{} task {} is not waiting for partial signatures",
+        line!(),
+        file!(),
+        &res.task_id
+    ))?;
+    info.insert_partial_signature(
+        peer_id,
+        &res.deployment_id,
+        partial_signature.index,
+        partial_signature.signature,
+    )?;
+    let complete = info.is_complete();
+    ExecutorStoreItem::save(&item)?;
+    if !complete {
+        return response_reply_with_subject(
+            "",
+            reply_to,
+            "partial signature received".as_bytes().to_vec(),
+        );
+    }
+
+    let info = item.threshold_sign.clone().ok_or(anyhow::anyhow!(
+        "{}:{} task {} is not waiting for partial signatures",
+        line!(),
+        file!(),
+        &res.task_id
+    ))?;
+    let p2_signature = aggregate_partial_signatures(&item, &info)?;
+    let delegator_peer_id = item.delegator_peer_id.clone();
+    commit_sign_result(
+        item,
+        &delegator_peer_id,
+        info.p1_signature,
+        info.public_keys,
+        p2_signature,
+    )?;
+    response_reply_with_subject("", reply_to, "signed successfully".as_bytes().to_vec())
+}
+
+fn commit_sign_result(
+    mut item: ExecutorStoreItem,
+    delegator_peer_id: &str,
+    p1_signature: Vec<u8>,
+    public_keys: Vec<Vec<u8>>,
+    p2_signature: Vec<u8>,
+) -> anyhow::Result<()> {
     debug!(
-        "recover and sign with p2 successfully, p2_signature: {:?}",
+        "sign with p2 successfully, p2_signature: {:?}",
         &p2_signature
     );
+    let task_id = item.task_info.task_id.clone();
 
-    // todo query p1, p2, p3 from layer1
-    let public_keys: Vec<Vec<u8>> = vec![];
-    let signatures = vec![p2_signature];
+    // signatures are ordered the same as public keys: P1, P2 (and P3 that does not sign)
+    let signatures = vec![p1_signature, p2_signature];
     let witness = combine_to_witness(
-        item.task_info.exec_info.k,
+        MULTI_SIG_THRESHOLD,
         public_keys,
         signatures,
         item.task_info.exec_info.task_type.clone(),
@@ -61,11 +175,91 @@ pub fn task_sign_with_key_slices_response_handler(
             ),
         ),
     };
-    send_message(peer_id, &task_id, req)?;
+    send_signed_message(delegator_peer_id, &task_id, req)?;
 
-    item.state = StoreItemState::Executed;
+    item.transition(StoreItemEvent::Executed, Some(delegator_peer_id))?;
     ExecutorStoreItem::save(&item)?;
-    response_reply_with_subject("", reply_to, "signed successfully".as_bytes().to_vec())
+    wipe_rsa_private_key(PREFIX_SIGN_RSA_KEY, &task_id)
+}
+
+/// P1 and P2 public keys are required to build the witness, a malformed response is rejected
+/// before it moves the task.
+fn check_public_keys(public_keys: &[Vec<u8>]) -> anyhow::Result<()> {
+    if public_keys.len() < 2 {
+        return Err(anyhow::anyhow!(
+            "{}:{} expect at least p1 and p2 public keys, got {}",
+            line!(),
+            file!(),
+            public_keys.len()
+        ));
+    }
+    if public_keys.iter().any(|v| v.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "{}:{} public keys should not be empty",
+            line!(),
+            file!()
+        ));
+    }
+    Ok(())
+}
+
+fn recover_and_sign(
+    item: &ExecutorStoreItem,
+    request: &crate::p2p_proto::TaskSignWithKeySlicesResponse,
+) -> anyhow::Result<Vec<u8>> {
+    let task_id = &item.task_info.task_id;
+    let key_slices = hybrid::decrypt_key_slices(
+        task_id,
+        &my_peer_id()?,
+        request.encrypted_key_slices.clone(),
+        |key| decrypt_key_slice(task_id, key),
+    )?;
+
+    let p2_private_key: SecretBuffer =
+        actor_crypto::shamir_recovery(item.task_info.exec_info.k, &expose_all(&key_slices))?.into();
+    drop(key_slices);
+
+    let p2_signature = actor_crypto::sign(
+        request.key_type.clone(),
+        p2_private_key.expose(),
+        request.adhoc_data.clone(),
+    )?;
+    drop(p2_private_key);
+    Ok(p2_signature)
+}
+
+/// P2 private key never exists on executor in threshold mode, partial signatures from pinners
+/// are aggregated and the result is checked against P2 public key.
+fn aggregate_partial_signatures(
+    item: &ExecutorStoreItem,
+    info: &ThresholdSignInfo,
+) -> anyhow::Result<Vec<u8>> {
+    let p2_signature = actor_crypto::threshold_aggregate(
+        info.key_type.clone(),
+        info.signers.iter().map(|v| v.index).collect(),
+        info.signers.iter().map(|v| v.commitment.clone()).collect(),
+        info.signers
+            .iter()
+            .map(|v| v.partial_signature.clone().unwrap_or_default())
+            .collect(),
+        info.adhoc_data.clone(),
+    )?;
+
+    let p2_public_key = info.public_keys[1].clone();
+    if !actor_crypto::verify(
+        info.key_type.clone(),
+        p2_public_key,
+        info.adhoc_data.clone(),
+        p2_signature.clone(),
+    )? {
+        return Err(anyhow::anyhow!(
+            "{}:{} aggregated signature of task {} is invalid",
+            line!(),
+            file!(),
+            &item.task_info.task_id
+        ));
+    }
+    Ok(p2_signature)
 }
 
 pub fn process_sign_with_key_slices_handler(
@@ -73,10 +267,11 @@ pub fn process_sign_with_key_slices_handler(
     req: crate::p2p_proto::SignCandidateRequest,
 ) -> anyhow::Result<()> {
     let mut store_item = ExecutorStoreItem::try_from(req)?;
+    store_item.delegator_peer_id = peer_id.to_string();
 
     // todo query ExecutionInfo from layer1 and update store item
 
-    if !willing_to_run(&store_item) {
+    if !willing_to_run(&store_item.task_info, &[TaskRole::Executor])? {
         info!(
             "I'm not willing to run {}, just ignore",
             &store_item.task_info.task_id
@@ -94,16 +289,11 @@ pub fn process_sign_with_key_slices_handler(
     ExecutorStoreItem::save(&store_item)?;
 
     send_sign_request(peer_id, &store_item.task_info.task_id)?;
-    store_item.state = StoreItemState::Requested;
+    store_item.transition(StoreItemEvent::Applied, Some(peer_id))?;
     ExecutorStoreItem::save(&store_item)?;
     Ok(())
 }
 
-fn willing_to_run(_item: &ExecutorStoreItem) -> bool {
-    // TODO check errand payment plan and i'm willing to run it
-    true
-}
-
 fn check_capabilities(_item: &ExecutorStoreItem) -> anyhow::Result<()> {
     // todo check if item.task_info.code_cid has deployed
 
@@ -113,12 +303,7 @@ fn check_capabilities(_item: &ExecutorStoreItem) -> anyhow::Result<()> {
 
 fn send_sign_request(peer_id: &str, task_id: &str) -> anyhow::Result<()> {
     let rsa_key_pkcs1 = generate_rsa_keypair()?;
-    actor_kvp::set(
-        BINDING_NAME,
-        &get_rsa_encrypt_key(task_id),
-        &rsa_key_pkcs1.private_key,
-        6000,
-    )?;
+    save_rsa_private_key(PREFIX_SIGN_RSA_KEY, task_id, &rsa_key_pkcs1.private_key)?;
 
     let req = crate::p2p_proto::TaskSignWithKeySlicesRequst {
         task_id: task_id.to_string(),
@@ -126,7 +311,7 @@ fn send_sign_request(peer_id: &str, task_id: &str) -> anyhow::Result<()> {
         cap_desc: None,
     };
 
-    send_message(
+    send_signed_message(
         peer_id,
         &task_id,
         crate::p2p_proto::GeneralMsg {
@@ -135,19 +320,19 @@ fn send_sign_request(peer_id: &str, task_id: &str) -> anyhow::Result<()> {
     )
 }
 
-fn get_rsa_encrypt_key(task_id: &str) -> String {
-    format!("{}_{}", PREFIX_SIGN_RSA_KEY, task_id)
+fn decrypt_key_slice(task_id: &str, key_slice_encrypted: Vec<u8>) -> anyhow::Result<SecretBuffer> {
+    rsa_decrypt_by_task(PREFIX_SIGN_RSA_KEY, task_id, key_slice_encrypted)
 }
 
-fn decrypt_key_slice(task_id: &str, key_slice_encrypted: Vec<u8>) -> anyhow::Result<Vec<u8>> {
-    let rsa_priv_key: String =
-        actor_kvp::get(BINDING_NAME, &get_rsa_encrypt_key(task_id))?.ok_or(anyhow::anyhow!(
-            "{}:{} could not find rsa pub key {} corresponding rsa private",
-            line!(),
-            file!(),
-            task_id
-        ))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let key_slice = rsa_decrypt(rsa_key_to_bytes(rsa_priv_key)?, key_slice_encrypted)?;
-    Ok(key_slice)
+    #[test]
+    fn p1_and_p2_public_keys_are_required() {
+        assert!(check_public_keys(&[vec![1], vec![2]]).is_ok());
+        assert!(check_public_keys(&[vec![1], vec![2], vec![3]]).is_ok());
+        assert!(check_public_keys(&[vec![1]]).is_err());
+        assert!(check_public_keys(&[vec![1], vec![]]).is_err());
+    }
 }
@@ -1,3 +1,6 @@
+use crate::common::retention::{self, TaskKind};
+use crate::common::state_machine::{self, StateMachine, Transition};
+use crate::common::utils::current_timestamp;
 use crate::common::TaskInfo;
 use crate::BINDING_NAME;
 use serde::export::TryFrom;
@@ -13,6 +16,38 @@ pub enum StoreItemState {
     Requested,
     Responded,
     Executed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum StoreItemEvent {
+    /// Sent the key generation or sign request to delegator
+    Applied,
+    /// Got the input to execute from delegator, retried requests keep the state
+    InputReceived,
+    Executed,
+    Cancelled,
+}
+
+impl StateMachine for StoreItemState {
+    type Event = StoreItemEvent;
+
+    fn next(&self, event: &StoreItemEvent) -> Option<Self> {
+        match (self, event) {
+            (StoreItemState::Init, StoreItemEvent::Applied) => Some(StoreItemState::Requested),
+            (StoreItemState::Requested, StoreItemEvent::InputReceived)
+            | (StoreItemState::Responded, StoreItemEvent::InputReceived) => {
+                Some(StoreItemState::Responded)
+            }
+            (StoreItemState::Responded, StoreItemEvent::Executed) => Some(StoreItemState::Executed),
+            (StoreItemState::Init, StoreItemEvent::Cancelled)
+            | (StoreItemState::Requested, StoreItemEvent::Cancelled)
+            | (StoreItemState::Responded, StoreItemEvent::Cancelled) => {
+                Some(StoreItemState::Cancelled)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -20,9 +55,171 @@ pub enum StoreItemState {
 pub struct ExecutorStoreItem {
     pub task_info: TaskInfo,
     pub state: StoreItemState,
+    /// Peer who invited me, only it can cancel the task through p2p
+    #[serde(default)]
+    pub delegator_peer_id: String,
+    #[serde(default)]
+    pub transitions: Vec<Transition<StoreItemState, StoreItemEvent>>,
+    #[serde(default)]
+    pub threshold_sign: Option<ThresholdSignInfo>,
+}
+
+/// Input of a threshold sign kept until all chosen pinners return their partial signatures.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThresholdSignInfo {
+    pub key_type: String,
+    pub adhoc_data: Vec<u8>,
+    pub p1_signature: Vec<u8>,
+    pub public_keys: Vec<Vec<u8>>,
+    pub signers: Vec<ThresholdSigner>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThresholdSigner {
+    pub peer_id: String,
+    pub deployment_id: String,
+    pub index: u32,
+    pub commitment: Vec<u8>,
+    pub partial_signature: Option<Vec<u8>>,
+}
+
+impl ThresholdSignInfo {
+    /// Chooses `k` pinners with distinct share indexes as signers. Every signer whose
+    /// commitment is used has to return its partial signature, so no more than `k` are chosen.
+    pub fn new(
+        request: &crate::p2p_proto::TaskSignWithKeySlicesResponse,
+        k: u8,
+    ) -> anyhow::Result<Self> {
+        let mut signers: Vec<ThresholdSigner> = Vec::new();
+        for commitment in request.signing_commitments.iter() {
+            if signers.len() == k as usize {
+                break;
+            }
+            if signers.iter().any(|v| v.index == commitment.index) {
+                continue;
+            }
+            signers.push(ThresholdSigner {
+                peer_id: commitment.peer_id.clone(),
+                deployment_id: commitment.deployment_id.clone(),
+                index: commitment.index,
+                commitment: commitment.commitment.clone(),
+                partial_signature: None,
+            });
+        }
+        if signers.len() < k as usize {
+            return Err(anyhow::anyhow!(
+                "{}:{} expect at least {} nonce commitments, got {}",
+                line!(),
+                file!(),
+                k,
+                signers.len()
+            ));
+        }
+        Ok(ThresholdSignInfo {
+            key_type: request.key_type.clone(),
+            adhoc_data: request.adhoc_data.clone(),
+            p1_signature: request.p1_signature.clone(),
+            public_keys: request.public_keys.clone(),
+            signers,
+        })
+    }
+
+    pub fn commitments(&self) -> Vec<crate::p2p_proto::SigningCommitment> {
+        self.signers
+            .iter()
+            .map(|v| crate::p2p_proto::SigningCommitment {
+                peer_id: v.peer_id.clone(),
+                deployment_id: v.deployment_id.clone(),
+                index: v.index,
+                commitment: v.commitment.clone(),
+            })
+            .collect()
+    }
+
+    /// Accepts the partial signature only from the pinner chosen for the deployment.
+    pub fn insert_partial_signature(
+        &mut self,
+        peer_id: &str,
+        deployment_id: &str,
+        index: u32,
+        signature: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let signer = self
+            .signers
+            .iter_mut()
+            .find(|v| v.peer_id == peer_id && v.deployment_id == deployment_id)
+            .ok_or(anyhow::anyhow!(
+                "{}:{} peer {} is not signer of deployment {}",
+                line!(),
+                file!(),
+                peer_id,
+                deployment_id
+            ))?;
+        if signer.index != index {
+            return Err(anyhow::anyhow!(
+                "{}:{} expect partial signature of index {}, got {}",
+                line!(),
+                file!(),
+                signer.index,
+                index
+            ));
+        }
+        signer.partial_signature = Some(signature);
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.signers.iter().all(|v| v.partial_signature.is_some())
+    }
 }
 
 impl ExecutorStoreItem {
+    pub fn is_terminal(&self) -> bool {
+        self.state == StoreItemState::Executed || self.state == StoreItemState::Cancelled
+    }
+
+    /// Only the delegator who invited me may drive the task through p2p.
+    pub fn check_delegator(&self, peer_id: &str) -> anyhow::Result<()> {
+        if self.delegator_peer_id != peer_id {
+            return Err(anyhow::anyhow!(
+                "{}:{} peer {} is not delegator of task {}",
+                line!(),
+                file!(),
+                peer_id,
+                &self.task_info.task_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves my executor part of the task by `event`. `peer_id` is the delegator the event is
+    /// exchanged with, `None` if layer1 cancelled the task.
+    pub fn transition(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.transition_at(event, peer_id, current_timestamp()?)
+    }
+
+    pub fn transition_at(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        state_machine::transit(
+            &self.task_info.task_id,
+            &mut self.state,
+            &mut self.transitions,
+            event,
+            peer_id,
+            timestamp,
+        )
+    }
+
     pub fn contains(task_id: &str) -> anyhow::Result<bool> {
         let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
         Ok(
@@ -32,11 +229,19 @@ impl ExecutorStoreItem {
     }
 
     pub fn get(task_id: &str) -> anyhow::Result<Self> {
-        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
-        actor_kvp::get::<ExecutorStoreItem>(BINDING_NAME, &get_task_store_item_key(task_id))?
+        ExecutorStoreItem::find(task_id)?
             .ok_or(TeaError::CommonError(format!("can not find task {}", task_id)).into())
     }
 
+    /// `None` if I never applied to execute the task, or its store item has been archived.
+    pub fn find(task_id: &str) -> anyhow::Result<Option<Self>> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(actor_kvp::get::<ExecutorStoreItem>(
+            BINDING_NAME,
+            &get_task_store_item_key(task_id),
+        )?)
+    }
+
     pub fn save(item: &ExecutorStoreItem) -> anyhow::Result<()> {
         let _lock = ShabbyLock::lock(BINDING_NAME, &item.task_info.task_id);
         actor_kvp::set_forever(
@@ -44,6 +249,16 @@ impl ExecutorStoreItem {
             &get_task_store_item_key(&item.task_info.task_id),
             item,
         )?;
+        retention::touch(TaskKind::Executor, &item.task_info.task_id)?;
+        if item.is_terminal() {
+            retention::mark_finished(TaskKind::Executor, &item.task_info.task_id, &item.state)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(task_id: &str) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        actor_kvp::del(BINDING_NAME, &get_task_store_item_key(task_id))?;
         Ok(())
     }
 }
@@ -57,6 +272,9 @@ impl TryFrom<crate::p2p_proto::KeyGenerationCandidateRequest> for ExecutorStoreI
         Ok(ExecutorStoreItem {
             task_info: TaskInfo::try_from(value)?,
             state: StoreItemState::Init,
+            delegator_peer_id: String::new(),
+            transitions: Vec::new(),
+            threshold_sign: None,
         })
     }
 }
@@ -68,6 +286,9 @@ impl TryFrom<crate::p2p_proto::SignCandidateRequest> for ExecutorStoreItem {
         Ok(ExecutorStoreItem {
             task_info: TaskInfo::from(value),
             state: StoreItemState::Init,
+            delegator_peer_id: String::new(),
+            transitions: Vec::new(),
+            threshold_sign: None,
         })
     }
 }
@@ -75,3 +296,77 @@ impl TryFrom<crate::p2p_proto::SignCandidateRequest> for ExecutorStoreItem {
 fn get_task_store_item_key(task_id: &str) -> String {
     format!("{}_{}", PREFIX_EXECUTOR_TASK_STORE_ITEM, task_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::state_machine::check_transition_table;
+
+    fn sign_request(indexes: &[u32]) -> crate::p2p_proto::TaskSignWithKeySlicesResponse {
+        crate::p2p_proto::TaskSignWithKeySlicesResponse {
+            signing_commitments: indexes
+                .iter()
+                .map(|index| crate::p2p_proto::SigningCommitment {
+                    peer_id: format!("pinner{}", index),
+                    deployment_id: format!("deployment{}", index),
+                    index: *index,
+                    commitment: vec![*index as u8],
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        use super::StoreItemEvent as E;
+        use super::StoreItemState as S;
+        check_transition_table(
+            &[
+                S::Init,
+                S::Requested,
+                S::Responded,
+                S::Executed,
+                S::Cancelled,
+            ],
+            &[E::Applied, E::InputReceived, E::Executed, E::Cancelled],
+            &[
+                (S::Init, E::Applied, S::Requested),
+                (S::Requested, E::InputReceived, S::Responded),
+                // retried input keeps the state
+                (S::Responded, E::InputReceived, S::Responded),
+                (S::Responded, E::Executed, S::Executed),
+                (S::Init, E::Cancelled, S::Cancelled),
+                (S::Requested, E::Cancelled, S::Cancelled),
+                (S::Responded, E::Cancelled, S::Cancelled),
+            ],
+        );
+    }
+
+    #[test]
+    fn exactly_k_signers_are_chosen() -> anyhow::Result<()> {
+        let info = ThresholdSignInfo::new(&sign_request(&[1, 1, 2, 3]), 2)?;
+        assert_eq!(
+            vec![1, 2],
+            info.signers.iter().map(|v| v.index).collect::<Vec<u32>>()
+        );
+        assert!(ThresholdSignInfo::new(&sign_request(&[1, 1]), 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn complete_after_all_signers_partial_sign() -> anyhow::Result<()> {
+        let mut info = ThresholdSignInfo::new(&sign_request(&[1, 2]), 2)?;
+        assert!(info
+            .insert_partial_signature("pinner3", "deployment1", 1, vec![1])
+            .is_err());
+        assert!(info
+            .insert_partial_signature("pinner1", "deployment1", 2, vec![1])
+            .is_err());
+        info.insert_partial_signature("pinner1", "deployment1", 1, vec![1])?;
+        assert!(!info.is_complete());
+        info.insert_partial_signature("pinner2", "deployment2", 2, vec![2])?;
+        assert!(info.is_complete());
+        Ok(())
+    }
+}
@@ -1,10 +1,16 @@
 use crate::{
-    common::{decrypt_key_slice, send_key_generation_request, verify_to_candidate_signature},
+    common::{
+        decrypt_key_slice, error::TaskError, hybrid, idempotency, payment::willing_to_run,
+        retention::TaskKind, secret::SecretBuffer, send_key_generation_request,
+        send_signed_message, utils::my_peer_id, verify_to_candidate_signature, vss,
+        wipe_key_generation_rsa_key, SignMode, TaskRole, TaskRoleRecord,
+    },
     executor::ExecutorStoreItem,
-    initial_pinner::store_item::StoreItemState,
+    initial_pinner::store_item::{StoreItemEvent, StoreItemState},
     BINDING_NAME,
 };
 use serde::export::TryFrom;
+use std::sync::Arc;
 use tea_actor_utility::{
     action,
     actor_crypto::{aes_encrypt, generate_aes_key},
@@ -13,7 +19,7 @@ use tea_actor_utility::{
     actor_nats::response_reply_with_subject,
     actor_util::{rsa_encrypt, rsa_key_to_bytes},
     encode_protobuf,
-    ipfs_p2p::{log_and_response_with_error, send_message},
+    ipfs_p2p::{log_and_response, log_and_response_with_error, P2pReplyType},
 };
 use wascc_actor::prelude::codec::messaging::BrokerMessage;
 use wascc_actor::HandlerResult;
@@ -25,6 +31,10 @@ const TEMP_DATA_CID_KEY_PREFIX: &'static str = "data-cid";
 
 pub fn trying_commit_data_upload(task_id: &str, multi_sig_account: &[u8]) -> anyhow::Result<()> {
     match InitialPinnerStoreItem::get(task_id) {
+        Ok(item) if item.state == StoreItemState::Cancelled => {
+            debug!("task {} is cancelled, do not commit its upload", task_id);
+            Ok(())
+        }
         Ok(_) => {
             let cid_code: String =
                 actor_kvp::get(BINDING_NAME, &get_temp_data_cid_key(multi_sig_account))?.ok_or(
@@ -98,23 +108,65 @@ pub fn task_pinner_key_slice_request_handler(
     peer_id: String,
     reply_to: String,
 ) -> anyhow::Result<()> {
-    match trying_get_initial_pinner_store_item(&req.task_id) {
+    let key = idempotency::message_key(
+        "TaskPinnerKeySliceRequest",
+        &peer_id,
+        &encode_protobuf(req.clone())?,
+    );
+    if !idempotency::claim(TaskKind::InitialPinner, &req.task_id, &key)? {
+        return idempotency::acknowledge_duplicate(&reply_to, &req.task_id);
+    }
+    let task_id = req.task_id.clone();
+    let result = pin_key_slice(req, peer_id, reply_to, key.clone());
+    idempotency::release_on_error(TaskKind::InitialPinner, &task_id, &key, result)
+}
+
+fn pin_key_slice(
+    req: crate::p2p_proto::TaskPinnerKeySliceRequest,
+    peer_id: String,
+    reply_to: String,
+    key: String,
+) -> anyhow::Result<()> {
+    match get_initial_pinner_store_item(&req.task_id, &peer_id) {
         Ok(mut item) => {
-            item.state = StoreItemState::Responded;
+            if item.is_terminal() {
+                return Err(TaskError::TaskExpired(item.task_info.task_id).into());
+            }
+            let key_slice = match decrypt_and_verify_key_slice(&item, &req) {
+                Ok(key_slice) => key_slice,
+                Err(e) => {
+                    item.transition(StoreItemEvent::KeySliceRejected, Some(peer_id.as_str()))?;
+                    InitialPinnerStoreItem::save(&item)?;
+                    idempotency::mark_processed(TaskKind::InitialPinner, &req.task_id, &key)?;
+                    wipe_key_generation_rsa_key(&req.task_id)?;
+                    return log_and_response(
+                        &reply_to,
+                        &peer_id,
+                        &req.task_id,
+                        &format!("reject key slice of task {}, details: {}", &req.task_id, e),
+                        P2pReplyType::Rejected,
+                    )
+                    .map_err(|e| anyhow::anyhow!("{}", e));
+                }
+            };
+            item.transition(StoreItemEvent::KeySliceAccepted, Some(peer_id.as_str()))?;
             InitialPinnerStoreItem::save(&item)?;
 
             let multi_sig_account = req.multi_sig_account.clone();
-            deploy_key_slice(req.clone(), move |deployment_id| {
+            deploy_key_slice(req.clone(), Arc::new(key_slice), move |deployment_id| {
                 actor_kvp::set(
                     BINDING_NAME,
                     &get_temp_deployment_key(&multi_sig_account),
                     &deployment_id,
                     6000,
                 )?;
-                item.state = StoreItemState::Deployed;
+                item.multi_sig_account = multi_sig_account.clone();
+                item.transition(StoreItemEvent::Deployed, None)?;
                 InitialPinnerStoreItem::save(&item)?;
+                idempotency::mark_processed(TaskKind::InitialPinner, &req.task_id, &key)?;
+                wipe_key_generation_rsa_key(&req.task_id)?;
 
-                send_message(
+                send_signed_message(
                     &peer_id,
                     &req.task_id,
                     crate::p2p_proto::GeneralMsg {
@@ -147,8 +199,37 @@ pub fn task_pinner_key_slice_request_handler(
     }
 }
 
+/// Decrypts the key slice and checks it against the share commitments published by the
+/// executor, so that a malicious executor can not make the network pin unrecoverable slices.
+/// Sign mode of the asset is deployed together with the key slice.
+fn decrypt_and_verify_key_slice(
+    item: &InitialPinnerStoreItem,
+    req: &crate::p2p_proto::TaskPinnerKeySliceRequest,
+) -> anyhow::Result<SecretBuffer> {
+    let key_slice = hybrid::decrypt_key_slice(
+        &req.task_id,
+        &my_peer_id()?,
+        &req.encrypted_key_slice,
+        |key| decrypt_key_slice(&req.task_id, key),
+    )?;
+    vss::verify_key_slice(
+        &item.task_info.exec_info.task_type,
+        key_slice.index,
+        &key_slice.key_slice,
+        &req.share_commitments,
+        &req.public_key,
+        item.task_info.exec_info.k,
+    )?;
+    hybrid::pack_deployed_key_slice(
+        &key_slice,
+        SignMode::from_threshold_flag(req.threshold_sign),
+        &item.task_info.exec_info.task_type,
+    )
+}
+
 fn deploy_key_slice<F>(
     req: crate::p2p_proto::TaskPinnerKeySliceRequest,
+    key_slice: Arc<SecretBuffer>,
     callback: F,
 ) -> anyhow::Result<()>
 where
@@ -170,9 +251,8 @@ where
             body: Vec::new(),
         },
         move |msg| {
-            let key_slice = decrypt_key_slice(&req.task_id, req.encrypted_key_slice.clone())?;
             let key1 = generate_aes_key()?;
-            let encrypted_data = aes_encrypt(key1.clone(), key_slice)?;
+            let encrypted_data = aes_encrypt(key1.clone(), key_slice.to_vec())?;
             let (data_cid, _) = ipfs_block_put(&encrypted_data, true)?;
 
             actor_kvp::set(
@@ -226,7 +306,8 @@ pub fn task_key_generation_candidate_request_handler(
     );
     verify_to_candidate_signature(&peer_id.clone(), &req.clone(), move || {
         let mut store_item = InitialPinnerStoreItem::try_from(req.clone())?;
-        if !willing_to_run(&store_item) {
+        store_item.delegator_peer_id = peer_id.clone();
+        if !willing_to_run(&store_item.task_info, &TaskRole::agreed_roles(false))? {
             info!(
                 "I'm not willing to run {}, just ignore",
                 &store_item.task_info.task_id
@@ -241,25 +322,28 @@ pub fn task_key_generation_candidate_request_handler(
             );
             return Ok(());
         }
+        let record = TaskRoleRecord::new(store_item.task_info.clone(), false);
+        TaskRoleRecord::save(&record)?;
         InitialPinnerStoreItem::save(&store_item)?;
 
-        send_key_generation_request(&peer_id, &store_item.task_info, false)?;
-        store_item.state = StoreItemState::Requested;
+        send_key_generation_request(&peer_id, &record)?;
+        store_item.transition(StoreItemEvent::Applied, Some(peer_id.as_str()))?;
         InitialPinnerStoreItem::save(&store_item)?;
         Ok(())
     })
 }
 
-fn willing_to_run(_item: &InitialPinnerStoreItem) -> bool {
-    // TODO check errand payment plan and i'm willing to run it
-    true
-}
-
 fn check_capabilities(_item: &InitialPinnerStoreItem) -> anyhow::Result<()> {
     // todo check if capabilities of my tea-box meets the request of task_info
     Ok(())
 }
 
+fn forget_uncommitted_upload(multi_sig_account: &[u8]) -> anyhow::Result<()> {
+    actor_kvp::del(BINDING_NAME, &get_temp_data_cid_key(multi_sig_account))?;
+    actor_kvp::del(BINDING_NAME, &get_temp_deployment_key(multi_sig_account))?;
+    Ok(())
+}
+
 fn get_temp_deployment_key(multi_sig_account: &[u8]) -> String {
     format!(
         "{}-{}",
@@ -276,12 +360,58 @@ fn get_temp_data_cid_key(multi_sig_account: &[u8]) -> String {
     )
 }
 
-fn trying_get_initial_pinner_store_item(task_id: &str) -> anyhow::Result<InitialPinnerStoreItem> {
-    match InitialPinnerStoreItem::get(task_id) {
-        Ok(item) => Ok(item),
-        Err(_) => match ExecutorStoreItem::get(task_id) {
-            Ok(item) => Ok(item.into()),
-            Err(e) => Err(e),
-        },
+/// Spare executor candidates may be chosen as initial pinners, they have no initial pinner store
+/// item until the key slice arrives. The role is taken only if the request comes from the
+/// delegator who invited me.
+fn get_initial_pinner_store_item(
+    task_id: &str,
+    delegator_peer_id: &str,
+) -> anyhow::Result<InitialPinnerStoreItem> {
+    let item = InitialPinnerStoreItem::get(task_id).ok();
+    match item.as_ref() {
+        Some(item) => item.check_delegator(delegator_peer_id)?,
+        None => ExecutorStoreItem::get(task_id)?.check_delegator(delegator_peer_id)?,
+    }
+    let record = TaskRoleRecord::take_role(task_id, TaskRole::InitialPinner)?;
+    match item {
+        Some(item) => Ok(item),
+        None => {
+            let mut item = InitialPinnerStoreItem {
+                task_info: record.task_info,
+                state: StoreItemState::Init,
+                delegator_peer_id: delegator_peer_id.to_string(),
+                multi_sig_account: Vec::new(),
+                transitions: Vec::new(),
+            };
+            // the key generation request was sent when applying to be executor
+            item.transition(StoreItemEvent::Applied, Some(delegator_peer_id))?;
+            Ok(item)
+        }
+    }
+}
+
+/// Cancels my initial pinner task and wipes its RSA key, `delegator_peer_id` is checked if the
+/// cancellation comes through p2p instead of layer1.
+///
+/// A deployed key slice whose upload is not committed yet is forgotten, so that it is never
+/// committed to the pinner actor. The encrypted block already put to IPFS is not removed.
+pub fn cancel_task(task_id: &str, delegator_peer_id: Option<&str>) -> anyhow::Result<()> {
+    let mut item = match InitialPinnerStoreItem::find(task_id)? {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    if let Some(peer_id) = delegator_peer_id {
+        item.check_delegator(peer_id)?;
+    }
+    if item.is_terminal() && item.state != StoreItemState::Deployed {
+        return Ok(());
+    }
+
+    info!("cancel initial pinner task {}", task_id);
+    if item.state == StoreItemState::Deployed {
+        forget_uncommitted_upload(&item.multi_sig_account)?;
     }
+    item.transition(StoreItemEvent::Cancelled, delegator_peer_id)?;
+    InitialPinnerStoreItem::save(&item)?;
+    wipe_key_generation_rsa_key(task_id)
 }
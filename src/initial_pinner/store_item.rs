@@ -1,5 +1,7 @@
+use crate::common::retention::{self, TaskKind};
+use crate::common::state_machine::{self, StateMachine, Transition};
+use crate::common::utils::current_timestamp;
 use crate::common::TaskInfo;
-use crate::executor::{ExecutorStoreItem, StoreItemState as ExecutorStoreItemState};
 use crate::BINDING_NAME;
 use serde::export::TryFrom;
 use tea_actor_utility::actor_kvp;
@@ -14,6 +16,45 @@ pub enum StoreItemState {
     Requested,
     Responded,
     Deployed,
+    Rejected,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum StoreItemEvent {
+    /// Sent the key generation request to delegator
+    Applied,
+    /// Key slice passed verification, retried requests keep the state
+    KeySliceAccepted,
+    KeySliceRejected,
+    Deployed,
+    Cancelled,
+}
+
+impl StateMachine for StoreItemState {
+    type Event = StoreItemEvent;
+
+    fn next(&self, event: &StoreItemEvent) -> Option<Self> {
+        match (self, event) {
+            (StoreItemState::Init, StoreItemEvent::Applied) => Some(StoreItemState::Requested),
+            (StoreItemState::Requested, StoreItemEvent::KeySliceAccepted)
+            | (StoreItemState::Responded, StoreItemEvent::KeySliceAccepted) => {
+                Some(StoreItemState::Responded)
+            }
+            (StoreItemState::Requested, StoreItemEvent::KeySliceRejected)
+            | (StoreItemState::Responded, StoreItemEvent::KeySliceRejected) => {
+                Some(StoreItemState::Rejected)
+            }
+            (StoreItemState::Responded, StoreItemEvent::Deployed) => Some(StoreItemState::Deployed),
+            (StoreItemState::Init, StoreItemEvent::Cancelled)
+            | (StoreItemState::Requested, StoreItemEvent::Cancelled)
+            | (StoreItemState::Responded, StoreItemEvent::Cancelled)
+            | (StoreItemState::Deployed, StoreItemEvent::Cancelled) => {
+                Some(StoreItemState::Cancelled)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -21,9 +62,65 @@ pub enum StoreItemState {
 pub struct InitialPinnerStoreItem {
     pub task_info: TaskInfo,
     pub state: StoreItemState,
+    /// Peer who invited me, only it can cancel the task through p2p
+    #[serde(default)]
+    pub delegator_peer_id: String,
+    /// Multi-sig account of the deployed key slice, its upload is forgotten if the task is
+    /// cancelled before the upload is committed
+    #[serde(default)]
+    pub multi_sig_account: Vec<u8>,
+    #[serde(default)]
+    pub transitions: Vec<Transition<StoreItemState, StoreItemEvent>>,
 }
 
 impl InitialPinnerStoreItem {
+    pub fn is_terminal(&self) -> bool {
+        match self.state {
+            StoreItemState::Deployed | StoreItemState::Rejected | StoreItemState::Cancelled => true,
+            _ => false,
+        }
+    }
+
+    /// Only the delegator who invited me may drive the task through p2p.
+    pub fn check_delegator(&self, peer_id: &str) -> anyhow::Result<()> {
+        if self.delegator_peer_id != peer_id {
+            return Err(anyhow::anyhow!(
+                "{}:{} peer {} is not delegator of task {}",
+                line!(),
+                file!(),
+                peer_id,
+                &self.task_info.task_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves my initial pinner part of the task by `event`. `peer_id` is the delegator the event
+    /// is exchanged with, `None` when the key slice is deployed or layer1 cancelled the task.
+    pub fn transition(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.transition_at(event, peer_id, current_timestamp()?)
+    }
+
+    pub fn transition_at(
+        &mut self,
+        event: StoreItemEvent,
+        peer_id: Option<&str>,
+        timestamp: u64,
+    ) -> anyhow::Result<()> {
+        state_machine::transit(
+            &self.task_info.task_id,
+            &mut self.state,
+            &mut self.transitions,
+            event,
+            peer_id,
+            timestamp,
+        )
+    }
+
     pub fn contains(task_id: &str) -> anyhow::Result<bool> {
         let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
         Ok(actor_kvp::get::<InitialPinnerStoreItem>(
@@ -34,11 +131,20 @@ impl InitialPinnerStoreItem {
     }
 
     pub fn get(task_id: &str) -> anyhow::Result<Self> {
-        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
-        actor_kvp::get::<InitialPinnerStoreItem>(BINDING_NAME, &get_task_store_item_key(task_id))?
+        InitialPinnerStoreItem::find(task_id)?
             .ok_or(TeaError::CommonError(format!("can not find task {}", task_id)).into())
     }
 
+    /// `None` if I never applied to be initial pinner of the task, or its store item has been
+    /// archived.
+    pub fn find(task_id: &str) -> anyhow::Result<Option<Self>> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        Ok(actor_kvp::get::<InitialPinnerStoreItem>(
+            BINDING_NAME,
+            &get_task_store_item_key(task_id),
+        )?)
+    }
+
     pub fn save(item: &InitialPinnerStoreItem) -> anyhow::Result<()> {
         let _lock = ShabbyLock::lock(BINDING_NAME, &item.task_info.task_id);
         actor_kvp::set_forever(
@@ -46,6 +152,20 @@ impl InitialPinnerStoreItem {
             &get_task_store_item_key(&item.task_info.task_id),
             item,
         )?;
+        retention::touch(TaskKind::InitialPinner, &item.task_info.task_id)?;
+        if item.is_terminal() {
+            retention::mark_finished(
+                TaskKind::InitialPinner,
+                &item.task_info.task_id,
+                &item.state,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(task_id: &str) -> anyhow::Result<()> {
+        let _lock = ShabbyLock::lock(BINDING_NAME, task_id);
+        actor_kvp::del(BINDING_NAME, &get_task_store_item_key(task_id))?;
         Ok(())
     }
 }
@@ -59,24 +179,56 @@ impl TryFrom<crate::p2p_proto::KeyGenerationCandidateRequest> for InitialPinnerS
         Ok(InitialPinnerStoreItem {
             task_info: TaskInfo::try_from(value)?,
             state: StoreItemState::Init,
+            delegator_peer_id: String::new(),
+            multi_sig_account: Vec::new(),
+            transitions: Vec::new(),
         })
     }
 }
 
-impl From<ExecutorStoreItem> for InitialPinnerStoreItem {
-    fn from(item: ExecutorStoreItem) -> Self {
-        InitialPinnerStoreItem {
-            task_info: item.task_info,
-            state: match item.state {
-                ExecutorStoreItemState::Init => StoreItemState::Init,
-                ExecutorStoreItemState::Requested => StoreItemState::Requested,
-                ExecutorStoreItemState::Responded => StoreItemState::Responded,
-                ExecutorStoreItemState::Executed => StoreItemState::Deployed,
-            },
-        }
-    }
-}
-
 fn get_task_store_item_key(task_id: &str) -> String {
     format!("{}_{}", PREFIX_INITIAL_PINNER_TASK_STORE_ITEM, task_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::state_machine::check_transition_table;
+
+    #[test]
+    fn transition_table() {
+        use super::StoreItemEvent as E;
+        use super::StoreItemState as S;
+        check_transition_table(
+            &[
+                S::Init,
+                S::Requested,
+                S::Responded,
+                S::Deployed,
+                S::Rejected,
+                S::Cancelled,
+            ],
+            &[
+                E::Applied,
+                E::KeySliceAccepted,
+                E::KeySliceRejected,
+                E::Deployed,
+                E::Cancelled,
+            ],
+            &[
+                (S::Init, E::Applied, S::Requested),
+                (S::Requested, E::KeySliceAccepted, S::Responded),
+                // retried key slice keeps the state
+                (S::Responded, E::KeySliceAccepted, S::Responded),
+                (S::Requested, E::KeySliceRejected, S::Rejected),
+                (S::Responded, E::KeySliceRejected, S::Rejected),
+                (S::Responded, E::Deployed, S::Deployed),
+                (S::Init, E::Cancelled, S::Cancelled),
+                (S::Requested, E::Cancelled, S::Cancelled),
+                (S::Responded, E::Cancelled, S::Cancelled),
+                // a deployed key slice is withdrawn if the task is cancelled
+                (S::Deployed, E::Cancelled, S::Cancelled),
+            ],
+        );
+    }
+}
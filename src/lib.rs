@@ -1,5 +1,7 @@
+use common::retention::TaskKind;
 use portal::{
-    asset_generated_event_handler, key_generation_request_handler, sign_with_key_slices_handler,
+    asset_generated_event_handler, key_generation_request_handler, reshare_request_handler,
+    sign_with_key_slices_handler, task_cancel_request_handler, task_status_query_handler,
 };
 use prost::Message;
 use tea_actor_utility::{
@@ -48,11 +50,21 @@ fn handle_message(msg: BrokerMessage) -> HandlerResult<()> {
             pinner_server_check_strategy(&msg)
         }
 
+        ["actor", MY_ACTOR_NAME, "event", "tick"] => periodic_tick(),
+        ["actor", MY_ACTOR_NAME, "command", "sweep_finished_tasks"] => {
+            sweep_finished_tasks_handler(&msg)
+        }
+        ["actor", MY_ACTOR_NAME, "query", "task_status"] => task_status_query_handler(&msg),
+
         ["layer1", "event", _, "AccountGenerationRequested"] => {
             key_generation_request_handler(&msg)
         }
         ["layer1", "event", _, "SignTransactionRequested"] => sign_with_key_slices_handler(&msg),
         ["layer1", "event", _, "AssetGenerated"] => asset_generated_event_handler(&msg),
+        ["layer1", "event", _, "KeySlicesReshareRequested"] => reshare_request_handler(&msg),
+        // new initial pinners commit their uploads the same way as a newly generated asset
+        ["layer1", "event", _, "KeySlicesReshared"] => asset_generated_event_handler(&msg),
+        ["layer1", "event", _, "TaskCancelRequested"] => task_cancel_request_handler(&msg),
         ["actor", MY_ACTOR_NAME, "inbox", uuid] => action::result_handler(&msg, uuid),
         ["reply", MY_ACTOR_NAME, uuid] => action::result_handler(&msg, uuid),
 
@@ -73,6 +85,102 @@ fn health(_req: codec::core::HealthRequest) -> HandlerResult<()> {
     Ok(())
 }
 
+/// Handles `actor.gluon.event.tick`. The actor has no timer of its own, the host must publish
+/// the tick periodically (more often than the candidate election timeout), otherwise timeout
+/// tasks are never failed and finished tasks are only archived by `sweep_finished_tasks`.
+fn periodic_tick() -> HandlerResult<()> {
+    let now = common::utils::current_timestamp()?;
+    // a failed timeout sweep must not keep finished tasks from being archived
+    if let Err(e) = delegator::sweep_key_gen_timeout_tasks(now) {
+        warn!(
+            "failed to sweep timeout key generation tasks, details: {}",
+            e
+        );
+    }
+    sweep_finished_tasks(now)?;
+    Ok(())
+}
+
+fn sweep_finished_tasks_handler(msg: &BrokerMessage) -> HandlerResult<()> {
+    let archived = sweep_finished_tasks(common::utils::current_timestamp()?)?;
+    info!("archived {} finished tasks on demand", archived);
+    if !msg.reply_to.is_empty() {
+        response_reply_with_subject("", &msg.reply_to, archived.to_string().into_bytes())?;
+    }
+    Ok(())
+}
+
+fn sweep_finished_tasks(now: u64) -> anyhow::Result<usize> {
+    common::retention::sweep_finished_tasks(now, |summary| {
+        match summary.kind {
+            TaskKind::DelegatorKeyGen => delegator::delete_key_gen_store_item(&summary.task_id)?,
+            TaskKind::DelegatorSign => delegator::delete_sign_store_item(&summary.task_id)?,
+            TaskKind::Executor => {
+                executor::ExecutorStoreItem::delete(&summary.task_id)?;
+                delete_unused_task_role_record(&summary.task_id)?;
+            }
+            TaskKind::InitialPinner => {
+                initial_pinner::InitialPinnerStoreItem::delete(&summary.task_id)?;
+                delete_unused_task_role_record(&summary.task_id)?;
+            }
+        }
+        common::idempotency::delete(summary.kind, &summary.task_id)
+    })
+}
+
+/// The role record is shared by my executor and initial pinner parts of a task, it is deleted
+/// with the RSA key of the task once neither part has a store item.
+fn delete_unused_task_role_record(task_id: &str) -> anyhow::Result<()> {
+    if executor::ExecutorStoreItem::contains(task_id)?
+        || initial_pinner::InitialPinnerStoreItem::contains(task_id)?
+        || !common::TaskRoleRecord::contains(task_id)?
+    {
+        return Ok(());
+    }
+    common::wipe_key_generation_rsa_key(task_id)?;
+    common::TaskRoleRecord::delete(task_id)
+}
+
+/// Tears down my executor and initial pinner parts of the task and wipes their RSA keys,
+/// `delegator_peer_id` is the sender if the cancellation comes through p2p. Both parts are
+/// cancelled even if one of them fails.
+fn cancel_participant_task(task_id: &str, delegator_peer_id: Option<&str>) -> anyhow::Result<()> {
+    let executor_result = executor::cancel_task(task_id, delegator_peer_id);
+    let initial_pinner_result = initial_pinner::cancel_task(task_id, delegator_peer_id);
+    match (executor_result, initial_pinner_result) {
+        (Err(e1), Err(e2)) => Err(anyhow::anyhow!(
+            "{}:{} failed to cancel executor part: {}, failed to cancel initial pinner part: {}",
+            line!(),
+            file!(),
+            e1,
+            e2
+        )),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn task_cancel_p2p_request_handler(
+    req: crate::p2p_proto::TaskCancelRequest,
+    peer_id: &str,
+    reply_to: &str,
+) -> anyhow::Result<()> {
+    let from_peer_id = peer_id.to_string();
+    let reply_to = reply_to.to_string();
+    common::verify_task_cancel_request(peer_id, &req.clone(), move || {
+        info!(
+            "task {} cancelled by delegator, reason: {}",
+            &req.task_id, &req.reason
+        );
+        cancel_participant_task(&req.task_id, Some(&from_peer_id))?;
+        Ok(response_reply_with_subject(
+            "",
+            &reply_to,
+            "task cancelled".as_bytes().to_vec(),
+        )?)
+    })
+}
+
 fn pinner_server_check_strategy(msg: &BrokerMessage) -> HandlerResult<()> {
     let res = crate::actor_pinner_proto::ServerCheckStrategy::decode(msg.body.as_slice())?;
     let item = res.item.ok_or(anyhow::anyhow!(
@@ -127,71 +235,115 @@ fn listen_p2p_message(from_peer_id: &str, msg: &BrokerMessage) -> HandlerResult<
         &from_peer_id.clone(),
         &msg,
         move |g_msg, from_peer_id, reply_to| match g_msg.msg.clone() {
-            Some(crate::p2p_proto::general_msg::Msg::TaskKeyGenerationApplyRequst(req)) => Ok(
-                delegator::task_key_generation_apply_request_handler(req, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskExecutionRequest(req)) => Ok(
-                executor::task_execution_request_handler(req, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskExecutionResponse(res)) => Ok(
-                delegator::task_execution_response_handler(res, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskPinnerKeySliceRequest(req)) => {
-                Ok(initial_pinner::task_pinner_key_slice_request_handler(
-                    req,
-                    from_peer_id.into(),
-                    reply_to.into(),
-                )?)
-            }
-            Some(crate::p2p_proto::general_msg::Msg::TaskPinnerKeySliceResponse(res)) => Ok(
-                delegator::task_pinner_key_slice_response_handler(res, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskSignWithKeySlicesRequst(req)) => Ok(
-                delegator::task_sign_with_key_slices_request_handler(req, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskSignWithKeySlicesResponse(res)) => Ok(
-                executor::task_sign_with_key_slices_response_handler(res, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceRequest(req)) => {
-                Ok(pinner::task_sign_with_key_slices_request_handler(
-                    req,
-                    from_peer_id.to_string(),
-                    reply_to.to_string(),
-                )?)
-            }
-            Some(crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceResponse(res)) => {
-                Ok(delegator::task_sign_get_pinner_key_slice_response_handler(
-                    res,
+            Some(crate::p2p_proto::general_msg::Msg::SignedMessage(signed)) => {
+                let peer_id = from_peer_id.to_string();
+                let reply_to = reply_to.to_string();
+                Ok(common::verify_signed_message(
                     from_peer_id,
-                    reply_to,
-                )?)
-            }
-            Some(crate::p2p_proto::general_msg::Msg::TaskCommitSignResultRequest(req)) => Ok(
-                delegator::task_commit_sign_result_request_handler(req, from_peer_id, reply_to)?,
-            ),
-            Some(crate::p2p_proto::general_msg::Msg::KeyGenerationCandidateRequest(req)) => {
-                Ok(match req.executor {
-                    true => executor::task_key_generation_candidate_request_handler(
-                        from_peer_id.to_string(),
-                        req,
-                    ),
-                    false => initial_pinner::task_key_generation_candidate_request_handler(
-                        from_peer_id.to_string(),
-                        req,
-                    ),
-                }?)
-            }
-            Some(crate::p2p_proto::general_msg::Msg::SignCandidateRequest(req)) => Ok(
-                executor::process_sign_with_key_slices_handler(from_peer_id, req)?,
-            ),
-            _ => {
-                trace!("Gluon actor unhandled p2p message type {:?}", &g_msg);
-                Ok(response_reply_with_subject(
-                    "",
-                    &reply_to,
-                    "Task actor unknown message".as_bytes().to_vec(),
+                    &signed,
+                    move |g_msg| dispatch_p2p_message(g_msg.msg, &peer_id, &reply_to, true),
                 )?)
             }
+            msg => dispatch_p2p_message(msg, from_peer_id, reply_to, false),
         },
     )?)
 }
+
+/// Task messages are accepted only if they came in a `SignedMessage` envelope, `signed` tells
+/// if `msg` did.
+fn dispatch_p2p_message(
+    msg: Option<crate::p2p_proto::general_msg::Msg>,
+    from_peer_id: &str,
+    reply_to: &str,
+    signed: bool,
+) -> HandlerResult<()> {
+    match msg {
+        // candidate and cancel requests carry their own signature of delegator
+        Some(crate::p2p_proto::general_msg::Msg::KeyGenerationCandidateRequest(req)) => {
+            Ok(match req.executor {
+                true => executor::task_key_generation_candidate_request_handler(
+                    from_peer_id.to_string(),
+                    req,
+                ),
+                false => initial_pinner::task_key_generation_candidate_request_handler(
+                    from_peer_id.to_string(),
+                    req,
+                ),
+            }?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskCancelRequest(req)) => Ok(
+            task_cancel_p2p_request_handler(req, from_peer_id, reply_to)?,
+        ),
+        Some(_) if !signed => {
+            warn!("reject unsigned task message from {}", from_peer_id);
+            Ok(response_reply_with_subject(
+                "",
+                &reply_to,
+                "unsigned task message".as_bytes().to_vec(),
+            )?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskKeyGenerationApplyRequst(req)) => Ok(
+            delegator::task_key_generation_apply_request_handler(req, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskExecutionRequest(req)) => Ok(
+            executor::task_execution_request_handler(req, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskExecutionResponse(res)) => Ok(
+            delegator::task_execution_response_handler(res, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskPinnerKeySliceRequest(req)) => {
+            Ok(initial_pinner::task_pinner_key_slice_request_handler(
+                req,
+                from_peer_id.into(),
+                reply_to.into(),
+            )?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskPinnerKeySliceResponse(res)) => Ok(
+            delegator::task_pinner_key_slice_response_handler(res, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskSignWithKeySlicesRequst(req)) => Ok(
+            delegator::task_sign_with_key_slices_request_handler(req, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskSignWithKeySlicesResponse(res)) => Ok(
+            executor::task_sign_with_key_slices_response_handler(res, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceRequest(req)) => {
+            Ok(pinner::task_sign_with_key_slices_request_handler(
+                req,
+                from_peer_id.to_string(),
+                reply_to.to_string(),
+            )?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceResponse(res)) => {
+            Ok(delegator::task_sign_get_pinner_key_slice_response_handler(
+                res,
+                from_peer_id,
+                reply_to,
+            )?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskPartialSignRequest(req)) => {
+            Ok(pinner::task_partial_sign_request_handler(
+                req,
+                from_peer_id.to_string(),
+                reply_to.to_string(),
+            )?)
+        }
+        Some(crate::p2p_proto::general_msg::Msg::TaskPartialSignResponse(res)) => Ok(
+            executor::task_partial_sign_response_handler(res, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::TaskCommitSignResultRequest(req)) => Ok(
+            delegator::task_commit_sign_result_request_handler(req, from_peer_id, reply_to)?,
+        ),
+        Some(crate::p2p_proto::general_msg::Msg::SignCandidateRequest(req)) => Ok(
+            executor::process_sign_with_key_slices_handler(from_peer_id, req)?,
+        ),
+        msg => {
+            trace!("Gluon actor unhandled p2p message type {:?}", &msg);
+            Ok(response_reply_with_subject(
+                "",
+                &reply_to,
+                "Task actor unknown message".as_bytes().to_vec(),
+            )?)
+        }
+    }
+}
@@ -1,3 +1,4 @@
 mod handler;
+mod signing_session;
 
-pub use handler::task_sign_with_key_slices_request_handler;
+pub use handler::{task_partial_sign_request_handler, task_sign_with_key_slices_request_handler};
@@ -1,6 +1,14 @@
+use super::signing_session::{self, SigningSession};
+use crate::common::{hybrid, secret::SecretBuffer, send_signed_message};
+use crate::delegator::{get_asset_info, get_sign_task};
+use std::sync::Arc;
 use tea_actor_utility::{
-    actor_crypto::aes_decrypt, actor_ipfs::ipfs_block_get, actor_nats::response_reply_with_subject,
-    actor_pinner::get_deployment_info, actor_util::rsa_encrypt, ipfs_p2p::send_message,
+    actor_crypto::{self, aes_decrypt, generate_aes_key},
+    actor_ipfs::ipfs_block_get,
+    actor_nats::response_reply_with_subject,
+    actor_pinner::get_deployment_info,
+    actor_util::rsa_encrypt,
+    ipfs_p2p::{log_and_response, P2pReplyType},
 };
 
 pub fn task_sign_with_key_slices_request_handler(
@@ -8,7 +16,291 @@ pub fn task_sign_with_key_slices_request_handler(
     peer_id: String,
     reply_to: String,
 ) -> anyhow::Result<()> {
+    match req.nonce_commit.is_some() {
+        false => return_key_slice(req, peer_id, reply_to),
+        true => {
+            let (req, peer_id, reply_to) = (Arc::new(req), Arc::new(peer_id), Arc::new(reply_to));
+            verify_sign_task(req.clone(), peer_id.clone(), reply_to.clone(), move || {
+                commit_nonces(req.clone(), peer_id.clone(), reply_to.clone())
+            })
+        }
+    }
+}
+
+fn return_key_slice(
+    req: crate::p2p_proto::TaskSignGetPinnerKeySliceRequest,
+    peer_id: String,
+    reply_to: String,
+) -> anyhow::Result<()> {
+    let deployment_id = req.deployment_id.clone();
+    with_deployed_key_slice(deployment_id.clone(), move |deployed| {
+        // the way a key slice is used is decided when it is deployed, not by the request
+        if deployed.sign_mode.is_threshold() {
+            return log_and_response(
+                &reply_to,
+                &peer_id,
+                &req.task_id,
+                &format!(
+                    "key slice of threshold deployment {} never leaves the pinner",
+                    &deployment_id
+                ),
+                P2pReplyType::Rejected,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e));
+        }
+        let aes_key: SecretBuffer = generate_aes_key()?.into();
+        let encrypted_key_slice: Vec<u8> = hybrid::encrypt_key_slice(
+            &aes_key,
+            &req.task_id,
+            &req.executor_peer_id,
+            deployed.key_slice.index,
+            deployed.key_slice.key_slice.expose(),
+            |key| Ok(rsa_encrypt(req.rsa_pub_key.clone(), key)?),
+        )?;
+        drop(deployed);
+        send_get_pinner_key_slice_response(
+            &peer_id,
+            &reply_to,
+            crate::p2p_proto::TaskSignGetPinnerKeySliceResponse {
+                task_id: req.task_id.clone(),
+                encrypted_key_slice,
+                deployment_id: deployment_id.clone(),
+                nonce_commitment: None,
+            },
+        )
+    })
+}
+
+/// First round of threshold sign, the nonces are kept in a signing session for the executor
+/// named by the delegator.
+fn commit_nonces(
+    req: Arc<crate::p2p_proto::TaskSignGetPinnerKeySliceRequest>,
+    peer_id: Arc<String>,
+    reply_to: Arc<String>,
+) -> anyhow::Result<()> {
+    let deployment_id = req.deployment_id.clone();
+    with_deployed_key_slice(deployment_id.clone(), move |deployed| {
+        if !deployed.sign_mode.is_threshold() {
+            return log_and_response(
+                &reply_to,
+                &peer_id,
+                &req.task_id,
+                &format!(
+                    "deployment {} is not deployed for threshold sign",
+                    &deployment_id
+                ),
+                P2pReplyType::Rejected,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e));
+        }
+        let nonce_commit = req.nonce_commit.as_ref().ok_or(anyhow::anyhow!(
+            "{}:{} nonce commit request of task {} is empty",
+            line!(),
+            file!(),
+            &req.task_id
+        ))?;
+        let (nonces, commitment) = actor_crypto::threshold_commit(
+            deployed.key_type.clone(),
+            deployed.key_slice.index,
+            deployed.key_slice.key_slice.expose(),
+        )?;
+        let nonces: SecretBuffer = nonces.into();
+        SigningSession::save(&SigningSession::new(
+            &req.task_id,
+            &deployment_id,
+            &req.executor_peer_id,
+            nonce_commit.adhoc_data.clone(),
+            deployed.key_slice.index,
+            commitment.clone(),
+            nonces.expose(),
+        ))?;
+        let index = deployed.key_slice.index;
+        drop(deployed);
+        send_get_pinner_key_slice_response(
+            &peer_id,
+            &reply_to,
+            crate::p2p_proto::TaskSignGetPinnerKeySliceResponse {
+                task_id: req.task_id.clone(),
+                encrypted_key_slice: Vec::new(),
+                deployment_id: deployment_id.clone(),
+                nonce_commitment: Some(crate::p2p_proto::NonceCommitment { index, commitment }),
+            },
+        )
+    })
+}
+
+/// A signing session is opened only for the delegator layer1 chose for the sign task, on a
+/// deployment of the signed asset, and over the transaction data P1 signed. Otherwise any peer
+/// could name itself executor and get partial signatures over data of its choice.
+fn verify_sign_task<F>(
+    req: Arc<crate::p2p_proto::TaskSignGetPinnerKeySliceRequest>,
+    peer_id: Arc<String>,
+    reply_to: Arc<String>,
+    callback: F,
+) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<()> + Clone + Send + Sync + 'static,
+{
+    get_sign_task(&req.task_id.clone(), move |sign_task| {
+        let (req, peer_id, reply_to) = (req.clone(), peer_id.clone(), reply_to.clone());
+        let callback = callback.clone();
+        let multi_sig_account = sign_task.sign_task.multi_sig_account.clone();
+        get_asset_info(multi_sig_account, move |asset_info| {
+            let adhoc_data = req
+                .nonce_commit
+                .as_ref()
+                .map(|v| v.adhoc_data.as_slice())
+                .unwrap_or_default();
+            let verified = signing_session::check_sign_task(
+                &sign_task,
+                &asset_info,
+                &peer_id,
+                &req.deployment_id,
+                &req.executor_peer_id,
+                adhoc_data,
+            )
+            .and_then(|_| verify_p1_signature(&sign_task, &asset_info));
+            if let Err(e) = verified {
+                return log_and_response(
+                    &reply_to,
+                    &peer_id,
+                    &req.task_id,
+                    &format!(
+                        "refuse to open signing session of task {}, details: {}",
+                        &req.task_id, e
+                    ),
+                    P2pReplyType::Rejected,
+                )
+                .map_err(|e| anyhow::anyhow!("{}", e));
+            }
+            callback()
+        })
+        .map_err(|e| anyhow::anyhow!("{}", e))
+    })
+    .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+/// Same check of P1 signature as the delegator does before starting the sign task.
+fn verify_p1_signature(
+    sign_task: &crate::actor_delegate_proto::GetSignTaskResponse,
+    asset_info: &crate::actor_delegate_proto::AssetInfo,
+) -> anyhow::Result<()> {
+    if !actor_crypto::verify(
+        asset_info.key_type.clone(),
+        asset_info.p1.clone(),
+        sign_task.sign_task.data_adhoc.transaction_data.clone(),
+        sign_task.sign_task.p1_signature.clone(),
+    )? {
+        return Err(anyhow::anyhow!(
+            "{}:{} p1 signature of the sign task is invalid",
+            line!(),
+            file!()
+        ));
+    }
+    Ok(())
+}
+
+fn send_get_pinner_key_slice_response(
+    peer_id: &str,
+    reply_to: &str,
+    response: crate::p2p_proto::TaskSignGetPinnerKeySliceResponse,
+) -> anyhow::Result<()> {
+    let task_id = response.task_id.clone();
+    send_signed_message(
+        peer_id,
+        &task_id,
+        crate::p2p_proto::GeneralMsg {
+            msg: Some(
+                crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceResponse(response),
+            ),
+        },
+    )?;
+    response_reply_with_subject("", reply_to, "key slice returned".as_bytes().to_vec())?;
+    Ok(())
+}
+
+/// Second round of threshold sign, the partial signature is made only for the executor the
+/// delegator elected in the first round, over the transaction data of the first round.
+pub fn task_partial_sign_request_handler(
+    req: crate::p2p_proto::TaskPartialSignRequest,
+    peer_id: String,
+    reply_to: String,
+) -> anyhow::Result<()> {
+    let session = match take_signing_session(&req, &peer_id) {
+        Ok(session) => session,
+        Err(e) => {
+            return log_and_response(
+                &reply_to,
+                &peer_id,
+                &req.task_id,
+                &format!(
+                    "refuse to partial sign task {}, details: {}",
+                    &req.task_id, e
+                ),
+                P2pReplyType::Rejected,
+            )
+            .map_err(|e| anyhow::anyhow!("{}", e));
+        }
+    };
+
     let deployment_id = req.deployment_id.clone();
+    with_deployed_key_slice(deployment_id.clone(), move |deployed| {
+        if !deployed.sign_mode.is_threshold() || deployed.key_slice.index != session.index {
+            return Err(anyhow::anyhow!(
+                "{}:{} deployment {} does not match signing session of task {}",
+                line!(),
+                file!(),
+                &deployment_id,
+                &req.task_id
+            ));
+        }
+        let signature = actor_crypto::threshold_partial_sign(
+            deployed.key_type.clone(),
+            deployed.key_slice.index,
+            deployed.key_slice.key_slice.expose(),
+            session.nonces(),
+            req.commitments.iter().map(|v| v.index).collect(),
+            req.commitments
+                .iter()
+                .map(|v| v.commitment.clone())
+                .collect(),
+            session.adhoc_data.clone(),
+        )?;
+        drop(deployed);
+        send_signed_message(
+            &peer_id,
+            &req.task_id,
+            crate::p2p_proto::GeneralMsg {
+                msg: Some(crate::p2p_proto::general_msg::Msg::TaskPartialSignResponse(
+                    crate::p2p_proto::TaskPartialSignResponse {
+                        task_id: req.task_id.clone(),
+                        deployment_id: deployment_id.clone(),
+                        partial_signature: Some(crate::p2p_proto::PartialSignature {
+                            index: session.index,
+                            signature,
+                        }),
+                    },
+                )),
+            },
+        )?;
+        response_reply_with_subject("", &reply_to, "partial signed".as_bytes().to_vec())?;
+        Ok(())
+    })
+}
+
+fn take_signing_session(
+    req: &crate::p2p_proto::TaskPartialSignRequest,
+    peer_id: &str,
+) -> anyhow::Result<SigningSession> {
+    let session = SigningSession::take(&req.task_id, &req.deployment_id, peer_id)?;
+    session.check_commitments(&req.commitments)?;
+    Ok(session)
+}
+
+fn with_deployed_key_slice<F>(deployment_id: String, callback: F) -> anyhow::Result<()>
+where
+    F: Fn(hybrid::DeployedKeySliceInfo) -> anyhow::Result<()> + Send + Sync + 'static,
+{
     get_deployment_info(
         crate::MY_ACTOR_NAME,
         &deployment_id.clone(),
@@ -18,26 +310,10 @@ pub fn task_sign_with_key_slices_request_handler(
                 data_cid.ok_or(anyhow::anyhow!("failed to get key1 of {}", &deployment_id))?;
 
             let encrypted_key_slice = ipfs_block_get(&data_cid)?;
-            let key_slice = aes_decrypt(key1, encrypted_key_slice)?;
-
-            let encrypted_key_slice: Vec<u8> = rsa_encrypt(req.rsa_pub_key.clone(), key_slice)?;
-            send_message(
-                &peer_id,
-                &req.task_id,
-                crate::p2p_proto::GeneralMsg {
-                    msg: Some(
-                        crate::p2p_proto::general_msg::Msg::TaskSignGetPinnerKeySliceResponse(
-                            crate::p2p_proto::TaskSignGetPinnerKeySliceResponse {
-                                task_id: req.task_id.clone(),
-                                encrypted_key_slice,
-                                deployment_id: deployment_id.clone(),
-                            },
-                        ),
-                    ),
-                },
-            )?;
-            response_reply_with_subject("", &reply_to, "key slice returned".as_bytes().to_vec())?;
-            Ok(())
+            let deployed: SecretBuffer = aes_decrypt(key1, encrypted_key_slice)?.into();
+            let key_slice = hybrid::unpack_deployed_key_slice(&deployed)?;
+            drop(deployed);
+            Ok(callback(key_slice)?)
         },
     )
 }
@@ -0,0 +1,237 @@
+use crate::BINDING_NAME;
+use tea_actor_utility::actor_kvp;
+use tea_actor_utility::actor_kvp::ShabbyLock;
+use zeroize::Zeroize;
+
+const PREFIX_PINNER_SIGNING_SESSION: &'static str = "pinner_signing_session";
+/// Nonces not used by the executor in time are dropped, the sign task has to start over
+const SIGNING_SESSION_EXPIRY_SECONDS: i32 = 300;
+
+/// First round of a threshold sign on my key slice. The nonces are committed to the delegator,
+/// only the executor elected by it may ask for the partial signature, over the transaction data
+/// given in the first round, and only once.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningSession {
+    pub task_id: String,
+    pub deployment_id: String,
+    pub executor_peer_id: String,
+    pub adhoc_data: Vec<u8>,
+    pub index: u32,
+    pub commitment: Vec<u8>,
+    nonces: Vec<u8>,
+}
+
+impl Drop for SigningSession {
+    fn drop(&mut self) {
+        self.nonces.zeroize();
+    }
+}
+
+impl SigningSession {
+    pub fn new(
+        task_id: &str,
+        deployment_id: &str,
+        executor_peer_id: &str,
+        adhoc_data: Vec<u8>,
+        index: u32,
+        commitment: Vec<u8>,
+        nonces: &[u8],
+    ) -> Self {
+        SigningSession {
+            task_id: task_id.to_string(),
+            deployment_id: deployment_id.to_string(),
+            executor_peer_id: executor_peer_id.to_string(),
+            adhoc_data,
+            index,
+            commitment,
+            nonces: nonces.to_vec(),
+        }
+    }
+
+    pub fn nonces(&self) -> &[u8] {
+        &self.nonces
+    }
+
+    pub fn save(session: &SigningSession) -> anyhow::Result<()> {
+        let key = get_signing_session_key(&session.task_id, &session.deployment_id);
+        let _lock = ShabbyLock::lock(BINDING_NAME, &key);
+        actor_kvp::set(BINDING_NAME, &key, session, SIGNING_SESSION_EXPIRY_SECONDS)?;
+        Ok(())
+    }
+
+    /// Removes the session for `peer_id` so that its nonces are never used twice. Sessions of
+    /// other executors are left untouched.
+    pub fn take(task_id: &str, deployment_id: &str, peer_id: &str) -> anyhow::Result<Self> {
+        let key = get_signing_session_key(task_id, deployment_id);
+        let _lock = ShabbyLock::lock(BINDING_NAME, &key);
+        let session =
+            actor_kvp::get::<SigningSession>(BINDING_NAME, &key)?.ok_or(anyhow::anyhow!(
+                "{}:{} no live signing session of task {} on {}",
+                line!(),
+                file!(),
+                task_id,
+                deployment_id
+            ))?;
+        session.check_executor(peer_id)?;
+        actor_kvp::del(BINDING_NAME, &key)?;
+        Ok(session)
+    }
+
+    pub fn check_executor(&self, peer_id: &str) -> anyhow::Result<()> {
+        if self.executor_peer_id != peer_id {
+            return Err(anyhow::anyhow!(
+                "{}:{} peer {} is not executor of task {}",
+                line!(),
+                file!(),
+                peer_id,
+                &self.task_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// The executor must sign with the commitment I made in the first round.
+    pub fn check_commitments(
+        &self,
+        commitments: &[crate::p2p_proto::SigningCommitment],
+    ) -> anyhow::Result<()> {
+        let mine = commitments
+            .iter()
+            .filter(|v| v.index == self.index)
+            .collect::<Vec<&crate::p2p_proto::SigningCommitment>>();
+        if mine.len() != 1 || mine[0].commitment != self.commitment {
+            return Err(anyhow::anyhow!(
+                "{}:{} commitments of task {} do not include mine",
+                line!(),
+                file!(),
+                &self.task_id
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a first round request from `peer_id` against the sign task recorded by layer1: the peer
+/// must be the delegator of the task, the deployment must belong to the signed asset and the data
+/// to sign must be the transaction data of the task.
+pub fn check_sign_task(
+    sign_task: &crate::actor_delegate_proto::GetSignTaskResponse,
+    asset_info: &crate::actor_delegate_proto::AssetInfo,
+    peer_id: &str,
+    deployment_id: &str,
+    executor_peer_id: &str,
+    adhoc_data: &[u8],
+) -> anyhow::Result<()> {
+    if sign_task.delegator_peer_id != peer_id {
+        return Err(anyhow::anyhow!(
+            "{}:{} peer {} is not delegator of the sign task",
+            line!(),
+            file!(),
+            peer_id
+        ));
+    }
+    if !asset_info
+        .p2_deployment_ids
+        .iter()
+        .any(|v| v == deployment_id)
+    {
+        return Err(anyhow::anyhow!(
+            "{}:{} deployment {} does not belong to the signed asset",
+            line!(),
+            file!(),
+            deployment_id
+        ));
+    }
+    if sign_task.sign_task.data_adhoc.transaction_data != adhoc_data {
+        return Err(anyhow::anyhow!(
+            "{}:{} data to sign is not the transaction data of the sign task",
+            line!(),
+            file!()
+        ));
+    }
+    if executor_peer_id.is_empty() {
+        return Err(anyhow::anyhow!("{}:{} executor is empty", line!(), file!()));
+    }
+    Ok(())
+}
+
+fn get_signing_session_key(task_id: &str, deployment_id: &str) -> String {
+    format!(
+        "{}_{}_{}",
+        PREFIX_PINNER_SIGNING_SESSION, task_id, deployment_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commitment(index: u32, commitment: Vec<u8>) -> crate::p2p_proto::SigningCommitment {
+        crate::p2p_proto::SigningCommitment {
+            peer_id: "pinner".into(),
+            deployment_id: "deployment".into(),
+            index,
+            commitment,
+        }
+    }
+
+    fn sign_task() -> crate::actor_delegate_proto::GetSignTaskResponse {
+        let mut sign_task = crate::actor_delegate_proto::GetSignTaskResponse::default();
+        sign_task.delegator_peer_id = "delegator".into();
+        sign_task.sign_task.data_adhoc.transaction_data = vec![1];
+        sign_task
+    }
+
+    fn asset_info() -> crate::actor_delegate_proto::AssetInfo {
+        crate::actor_delegate_proto::AssetInfo {
+            p2_deployment_ids: vec!["deployment".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_delegator_opens_session_over_transaction_data() {
+        let check = |peer_id, deployment_id, executor_peer_id, adhoc_data: &[u8]| {
+            check_sign_task(
+                &sign_task(),
+                &asset_info(),
+                peer_id,
+                deployment_id,
+                executor_peer_id,
+                adhoc_data,
+            )
+        };
+        assert!(check("delegator", "deployment", "executor", &[1]).is_ok());
+        assert!(check("executor", "deployment", "executor", &[1]).is_err());
+        assert!(check("delegator", "another deployment", "executor", &[1]).is_err());
+        assert!(check("delegator", "deployment", "executor", &[2]).is_err());
+        assert!(check("delegator", "deployment", "", &[1]).is_err());
+    }
+
+    #[test]
+    fn only_elected_executor_can_sign() {
+        let session = SigningSession::new("task", "deployment", "executor", vec![], 1, vec![], &[]);
+        assert!(session.check_executor("executor").is_ok());
+        assert!(session.check_executor("another executor").is_err());
+    }
+
+    #[test]
+    fn commitments_must_include_mine() {
+        // my key slice index is 2 and I committed [3]
+        let session =
+            SigningSession::new("task", "deployment", "executor", vec![], 2, vec![3], &[]);
+        assert!(session
+            .check_commitments(&[commitment(1, vec![9]), commitment(2, vec![3])])
+            .is_ok());
+        assert!(session
+            .check_commitments(&[commitment(1, vec![9])])
+            .is_err());
+        assert!(session
+            .check_commitments(&[commitment(2, vec![9])])
+            .is_err());
+        assert!(session
+            .check_commitments(&[commitment(2, vec![3]), commitment(2, vec![3])])
+            .is_err());
+    }
+}
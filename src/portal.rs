@@ -1,5 +1,8 @@
 mod layer1_events;
+mod task_status;
 
 pub use layer1_events::{
-    asset_generated_event_handler, key_generation_request_handler, sign_with_key_slices_handler,
+    asset_generated_event_handler, key_generation_request_handler, reshare_request_handler,
+    sign_with_key_slices_handler, task_cancel_request_handler,
 };
+pub use task_status::task_status_query_handler;
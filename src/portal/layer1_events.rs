@@ -1,4 +1,6 @@
-use crate::delegator::process_key_generation_event;
+use crate::delegator::{
+    process_key_generation_event, process_reshare_event, process_task_cancel_event,
+};
 use crate::initial_pinner::{trying_commit_data_upload, update_conflict_list};
 use prost::Message;
 use tea_actor_utility::actor_pinner::is_node_ready;
@@ -47,6 +49,23 @@ pub fn sign_with_key_slices_handler(msg: &BrokerMessage) -> HandlerResult<()> {
     })?)
 }
 
+pub fn reshare_request_handler(msg: &BrokerMessage) -> HandlerResult<()> {
+    let base64_decoded_msg_body = base64::decode(String::from_utf8(msg.body.clone())?)?;
+    Ok(is_node_ready(crate::MY_ACTOR_NAME, move |ready| {
+        if !ready {
+            debug!("node is not ready, just ignore layer1 KeySlicesReshareRequested request");
+            return Ok(());
+        }
+
+        let reshare_response = crate::actor_delegate_proto::ReshareResponse::decode(
+            base64_decoded_msg_body.as_slice(),
+        )?;
+        trace!("ReshareResponse protobuf decoded {:?}", &reshare_response);
+        process_reshare_event(reshare_response)?;
+        Ok(())
+    })?)
+}
+
 pub fn asset_generated_event_handler(msg: &BrokerMessage) -> HandlerResult<()> {
     let base64_decoded_msg_body = base64::decode(String::from_utf8(msg.body.clone())?)?;
     let res = crate::actor_delegate_proto::AssetGeneratedResponse::decode(
@@ -58,3 +77,29 @@ pub fn asset_generated_event_handler(msg: &BrokerMessage) -> HandlerResult<()> {
 
     Ok(())
 }
+
+pub fn task_cancel_request_handler(msg: &BrokerMessage) -> HandlerResult<()> {
+    let base64_decoded_msg_body = base64::decode(String::from_utf8(msg.body.clone())?)?;
+    Ok(is_node_ready(crate::MY_ACTOR_NAME, move |ready| {
+        if !ready {
+            debug!("node is not ready, just ignore layer1 TaskCancelRequested request");
+            return Ok(());
+        }
+
+        let res = crate::actor_delegate_proto::TaskCancelResponse::decode(
+            base64_decoded_msg_body.as_slice(),
+        )?;
+        debug!("task_cancel_request_handler got response: {:?}", res);
+        // layer1 is trusted, participants tear down their part without waiting for the delegator,
+        // and a failure of my participant part must not keep the delegator from notifying others
+        let task_id = base64::encode(&res.task_id);
+        if let Err(e) = crate::cancel_participant_task(&task_id, None) {
+            warn!(
+                "failed to cancel participant part of task {}, details: {}",
+                &task_id, e
+            );
+        }
+        process_task_cancel_event(res)?;
+        Ok(())
+    })?)
+}
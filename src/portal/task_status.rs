@@ -0,0 +1,93 @@
+use crate::common::retention::{self, TaskKind, TaskSummary, TaskTimestamps};
+use crate::common::TaskRoleRecord;
+use crate::executor::ExecutorStoreItem;
+use crate::initial_pinner::InitialPinnerStoreItem;
+use prost::Message;
+use tea_actor_utility::{actor_nats::response_reply_with_subject, encode_protobuf};
+use wascc_actor::prelude::codec::messaging::BrokerMessage;
+use wascc_actor::HandlerResult;
+
+/// Replies the roles this node plays in a task with their store item states, so that
+/// operators can find out what a stuck task is doing.
+pub fn task_status_query_handler(msg: &BrokerMessage) -> HandlerResult<()> {
+    let req = crate::actor_delegate_proto::TaskStatusRequest::decode(msg.body.as_slice())?;
+    let task_id = req.task_id.clone();
+    debug!("task_status_query_handler got request of task {}", &task_id);
+
+    let mut statuses = Vec::new();
+    let states = vec![
+        (
+            TaskKind::DelegatorKeyGen,
+            crate::delegator::key_gen_task_state(&task_id)?,
+        ),
+        (
+            TaskKind::DelegatorSign,
+            crate::delegator::sign_task_state(&task_id)?,
+        ),
+        (
+            TaskKind::Executor,
+            ExecutorStoreItem::find(&task_id)?.map(|v| format!("{:?}", v.state)),
+        ),
+        (
+            TaskKind::InitialPinner,
+            InitialPinnerStoreItem::find(&task_id)?.map(|v| format!("{:?}", v.state)),
+        ),
+    ];
+    for (kind, state) in states {
+        if let Some(status) = role_status(kind, &task_id, state)? {
+            statuses.push(status);
+        }
+    }
+
+    let (agreed_roles, taken_role) = match TaskRoleRecord::find(&task_id)? {
+        Some(record) => (
+            record.agreed.iter().map(|v| format!("{:?}", v)).collect(),
+            record.taken.map(|v| format!("{:?}", v)).unwrap_or_default(),
+        ),
+        None => (Vec::new(), String::new()),
+    };
+
+    Ok(response_reply_with_subject(
+        "",
+        &msg.reply_to,
+        encode_protobuf(crate::actor_delegate_proto::TaskStatusResponse {
+            task_id,
+            statuses,
+            agreed_roles,
+            taken_role,
+        })?,
+    )?)
+}
+
+/// Status of a live store item, or of its summary if the store item has been archived.
+fn role_status(
+    kind: TaskKind,
+    task_id: &str,
+    state: Option<String>,
+) -> anyhow::Result<Option<crate::actor_delegate_proto::TaskRoleStatus>> {
+    let role = format!("{:?}", kind);
+    if let Some(state) = state {
+        let (created_at, updated_at) = TaskTimestamps::get(kind, task_id)?
+            .map(|v| (v.created_at, v.updated_at))
+            .unwrap_or_default();
+        let finished_at = retention::finished_at(kind, task_id)?;
+        return Ok(Some(crate::actor_delegate_proto::TaskRoleStatus {
+            role,
+            state,
+            created_at,
+            updated_at,
+            finished_at: finished_at.unwrap_or_default(),
+            archived: false,
+        }));
+    }
+    Ok(TaskSummary::get(kind, task_id)?.map(|summary| {
+        crate::actor_delegate_proto::TaskRoleStatus {
+            role,
+            state: summary.state,
+            created_at: summary.created_at,
+            updated_at: summary.finished_at,
+            finished_at: summary.finished_at,
+            archived: true,
+        }
+    }))
+}